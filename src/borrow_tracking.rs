@@ -0,0 +1,352 @@
+//! Hooks that run when guards of the checked backend are created and dropped.
+//!
+//! Each guard holds a `BorrowToken`. With the `track-borrows` feature, it registers the location of the borrow in a
+//! thread-local table keyed by the address of the contents, and removes it again when the guard is dropped, so the
+//! table only ever holds instances that are currently borrowed. With the `async` feature, dropping it wakes the tasks
+//! waiting in `SCell::borrow_mut_async`. With the `borrow-stats` feature, it counts the borrow in another thread-local
+//! table keyed the same way, whose entries hold a weak handle to their instance so that its address can't be reused
+//! by another instance while the entry exists. Entries of dropped instances are swept out whenever the table would
+//! otherwise grow. Without any of these features, `BorrowToken` is empty and does nothing.
+//!
+//! This also holds the hook that `track-borrows` runs before a conflicting `borrow` or `borrow_mut` panics.
+
+#[cfg(feature = "track-borrows")]
+use core::panic::Location;
+#[cfg(any(feature = "track-borrows", feature = "borrow-stats"))]
+use std::cell::RefCell;
+#[cfg(any(feature = "track-borrows", feature = "borrow-stats"))]
+use std::collections::HashMap;
+#[cfg(feature = "borrow-stats")]
+use core::cmp;
+#[cfg(feature = "borrow-stats")]
+use alloc::rc::{Rc, Weak};
+#[cfg(feature = "borrow-stats")]
+use alloc::boxed::Box;
+#[cfg(feature = "track-borrows")]
+use alloc::vec::Vec;
+#[cfg(feature = "track-borrows")]
+use core::fmt::{Formatter, Display, Error};
+#[cfg(feature = "track-borrows")]
+use std::sync::{Mutex, PoisonError};
+
+/// The locations of the outstanding borrows of each instance that is currently borrowed, keyed by its address
+#[cfg(feature = "track-borrows")]
+type Borrows = HashMap<*const (), Vec<&'static Location<'static>>>;
+
+#[cfg(feature = "track-borrows")]
+thread_local!(static BORROWS: RefCell<Borrows> = RefCell::new(HashMap::new()));
+
+/// How an instance has been borrowed over its lifetime, returned by `SCell::borrow_stats`
+#[cfg(feature = "borrow-stats")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BorrowStats {
+    total: usize,
+    max_concurrent: usize,
+}
+
+#[cfg(feature = "borrow-stats")]
+impl BorrowStats {
+    /// Gets the number of guards that have been created, counting clones and splits of guards
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Gets the most guards that have been alive at the same time
+    #[inline]
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+}
+
+/// A weak handle to an instance with its type erased, so that entries for instances of any type fit in one table
+///
+/// The handle is boxed so that the pointer to it is thin even for unsized contents. Dropping a `Weak` never touches the
+/// contents, so it is fine for `T` to have gone out of scope by the time the table drops this.
+#[cfg(feature = "borrow-stats")]
+struct Instance {
+    weak: *mut (),
+    alive: unsafe fn(*mut ()) -> bool,
+    release: unsafe fn(*mut ()),
+}
+
+#[cfg(feature = "borrow-stats")]
+impl Instance {
+    fn new<T: ?Sized>(rc: &Rc<RefCell<T>>) -> Self {
+        unsafe fn alive<T: ?Sized>(weak: *mut ()) -> bool {
+            (*(weak as *const Weak<RefCell<T>>)).strong_count() != 0
+        }
+        unsafe fn release<T: ?Sized>(weak: *mut ()) {
+            drop(Box::from_raw(weak as *mut Weak<RefCell<T>>));
+        }
+        Instance {
+            weak: Box::into_raw(Box::new(Rc::downgrade(rc))) as *mut (),
+            alive: alive::<T>,
+            release: release::<T>,
+        }
+    }
+
+    #[inline]
+    fn alive(&self) -> bool {
+        unsafe{(self.alive)(self.weak)}
+    }
+}
+
+#[cfg(feature = "borrow-stats")]
+impl Drop for Instance {
+    fn drop(&mut self) {
+        unsafe{(self.release)(self.weak)}
+    }
+}
+
+/// The stats of an instance that has been borrowed, along with its number of outstanding borrows
+#[cfg(feature = "borrow-stats")]
+struct Entry {
+    stats: BorrowStats,
+    outstanding: usize,
+    instance: Instance,
+}
+
+/// The stats of each instance that has been borrowed, keyed by its address
+#[cfg(feature = "borrow-stats")]
+type Stats = HashMap<*const (), Entry>;
+
+#[cfg(feature = "borrow-stats")]
+thread_local!(static STATS: RefCell<Stats> = RefCell::new(HashMap::new()));
+
+/// The address of the borrowed contents, which is only kept if a feature needs it
+#[cfg(any(feature = "track-borrows", feature = "async", feature = "borrow-stats"))]
+type Addr = *const ();
+#[cfg(not(any(feature = "track-borrows", feature = "async", feature = "borrow-stats")))]
+#[derive(Clone, Copy)]
+struct Addr;
+
+/// Where a borrow was taken, which is only kept with the `track-borrows` feature
+#[cfg(feature = "track-borrows")]
+type Site = &'static Location<'static>;
+#[cfg(not(feature = "track-borrows"))]
+#[derive(Clone, Copy)]
+struct Site;
+
+#[inline]
+fn addr(ptr: *const ()) -> Addr {
+    #[cfg(any(feature = "track-borrows", feature = "async", feature = "borrow-stats"))]
+    let addr = ptr;
+    #[cfg(not(any(feature = "track-borrows", feature = "async", feature = "borrow-stats")))]
+    let addr = {
+        let _ = ptr;
+        Addr
+    };
+    addr
+}
+
+#[inline]
+#[track_caller]
+fn caller() -> Site {
+    #[cfg(feature = "track-borrows")]
+    let site = Location::caller();
+    #[cfg(not(feature = "track-borrows"))]
+    let site = Site;
+    site
+}
+
+/// Registers a borrow of the contents at an address for as long as it lives
+pub struct BorrowToken {
+    addr: Addr,
+    location: Site,
+}
+
+impl BorrowToken {
+    /// Registers a borrow of the contents at `ptr` taken by the caller
+    #[inline]
+    #[track_caller]
+    pub fn new(ptr: *const ()) -> Self {
+        BorrowToken::at(addr(ptr), caller())
+    }
+
+    /// Registers another borrow of the same contents taken by the caller, for guards that are cloned from this one
+    #[inline]
+    #[track_caller]
+    pub fn reborrow(&self) -> Self {
+        BorrowToken::at(self.addr, caller())
+    }
+
+    /// Registers another borrow of the same contents at the same location, for guards that are split from this one
+    #[inline]
+    pub fn duplicate(&self) -> Self {
+        BorrowToken::at(self.addr, self.location)
+    }
+
+    #[inline]
+    fn at(addr: Addr, location: Site) -> Self {
+        #[cfg(feature = "track-borrows")]
+        BORROWS.with(|borrows| borrows.borrow_mut().entry(addr).or_default().push(location));
+        #[cfg(feature = "borrow-stats")]
+        STATS.with(|stats| {
+            if let Some(entry) = stats.borrow_mut().get_mut(&addr) {
+                entry.outstanding += 1;
+                entry.stats.total += 1;
+                entry.stats.max_concurrent = cmp::max(entry.stats.max_concurrent, entry.outstanding);
+            }
+        });
+        BorrowToken { addr, location }
+    }
+}
+
+#[cfg(any(feature = "track-borrows", feature = "async", feature = "borrow-stats"))]
+impl Drop for BorrowToken {
+    fn drop(&mut self) {
+        #[cfg(feature = "track-borrows")]
+        BORROWS.with(|borrows| {
+            let mut borrows = borrows.borrow_mut();
+            if let Some(locations) = borrows.get_mut(&self.addr) {
+                if let Some(index) = locations.iter().position(|&location| location == self.location) {
+                    locations.swap_remove(index);
+                }
+                if locations.is_empty() {
+                    borrows.remove(&self.addr);
+                }
+            }
+        });
+        #[cfg(feature = "borrow-stats")]
+        STATS.with(|stats| {
+            if let Some(entry) = stats.borrow_mut().get_mut(&self.addr) {
+                entry.outstanding -= 1;
+            }
+        });
+        #[cfg(feature = "async")]
+        ::waiters::wake(self.addr);
+    }
+}
+
+/// Gets the locations of the outstanding borrows of the contents at `addr`
+#[cfg(feature = "track-borrows")]
+pub fn held(addr: *const ()) -> Vec<&'static Location<'static>> {
+    BORROWS.with(|borrows| borrows.borrow().get(&addr).cloned().unwrap_or_default())
+}
+
+/// Formats a list of locations separated by commas
+#[cfg(feature = "track-borrows")]
+pub struct Locations<'a>(pub &'a [&'static Location<'static>]);
+
+#[cfg(feature = "track-borrows")]
+impl<'a> Display for Locations<'a> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        for (i, location) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            location.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Which kind of borrow failed, reported by `BorrowFailure::kind`
+#[cfg(feature = "track-borrows")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BorrowKind {
+    /// A `borrow`, which conflicted with a mutable borrow
+    Shared,
+    /// A `borrow_mut`, which conflicted with any borrow
+    Mutable,
+}
+
+/// A borrow that is about to panic, passed to the hook set with `set_borrow_panic_hook`
+#[cfg(feature = "track-borrows")]
+#[derive(Debug)]
+pub struct BorrowFailure {
+    kind: BorrowKind,
+    attempted: &'static Location<'static>,
+    held: Vec<&'static Location<'static>>,
+}
+
+#[cfg(feature = "track-borrows")]
+impl BorrowFailure {
+    /// Gets which kind of borrow failed
+    #[inline]
+    pub fn kind(&self) -> BorrowKind {
+        self.kind
+    }
+
+    /// Gets where the borrow that failed was attempted
+    #[inline]
+    pub fn attempted(&self) -> &'static Location<'static> {
+        self.attempted
+    }
+
+    /// Gets where the outstanding borrows that it conflicted with were taken
+    #[inline]
+    pub fn held(&self) -> &[&'static Location<'static>] {
+        &self.held
+    }
+}
+
+#[cfg(feature = "track-borrows")]
+static PANIC_HOOK: Mutex<Option<fn(&BorrowFailure)>> = Mutex::new(None);
+
+/// Sets a function for the checked backend to call right before `borrow` or `borrow_mut` panics because of a conflict,
+/// replacing the previous one
+///
+/// The hook is shared by every thread, and the panic still happens after it returns. Only the panicking `borrow` and
+/// `borrow_mut` call it, not `try_borrow` and `try_borrow_mut`.
+#[cfg(feature = "track-borrows")]
+pub fn set_borrow_panic_hook(hook: fn(&BorrowFailure)) {
+    *PANIC_HOOK.lock().unwrap_or_else(PoisonError::into_inner) = Some(hook);
+}
+
+/// Runs the panic hook, then panics with a message for `kind` and the locations of the outstanding borrows of the
+/// contents at `addr`
+#[cfg(feature = "track-borrows")]
+#[cold]
+#[track_caller]
+pub fn conflict(addr: *const (), kind: BorrowKind) -> ! {
+    let failure = BorrowFailure {
+        kind,
+        attempted: Location::caller(),
+        held: held(addr),
+    };
+    // The lock is released before the hook runs, so that a hook that borrows a cell and panics can't deadlock.
+    let hook = *PANIC_HOOK.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(hook) = hook {
+        hook(&failure);
+    }
+    let message = match kind {
+        BorrowKind::Shared => "already mutably borrowed",
+        BorrowKind::Mutable => "already borrowed",
+    };
+    if failure.held.is_empty() {
+        panic!("{}", message)
+    } else {
+        panic!("{} (held at {})", message, Locations(&failure.held))
+    }
+}
+
+/// Makes sure the instance behind `rc`, whose contents are at `addr`, has an entry in the stats table
+///
+/// An existing entry at `addr` always belongs to this instance, since its weak handle keeps the allocation of any
+/// earlier instance from being freed and reused. Dropped instances are swept out right before the table would grow,
+/// which keeps the sweeps amortized over the insertions.
+#[cfg(feature = "borrow-stats")]
+pub fn register<T: ?Sized>(addr: *const (), rc: &Rc<RefCell<T>>) {
+    STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        if stats.contains_key(&addr) {
+            return;
+        }
+        if stats.len() == stats.capacity() {
+            stats.retain(|_, entry| entry.instance.alive());
+        }
+        stats.insert(addr, Entry {
+            stats: BorrowStats::default(),
+            outstanding: 0,
+            instance: Instance::new(rc),
+        });
+    });
+}
+
+/// Gets the stats of the instance at `addr`
+#[cfg(feature = "borrow-stats")]
+pub fn stats(addr: *const ()) -> BorrowStats {
+    STATS.with(|stats| stats.borrow().get(&addr).map(|entry| entry.stats).unwrap_or_default())
+}
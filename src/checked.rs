@@ -1,8 +1,18 @@
-use std::rc::Rc;
-use std::cell;
-use std::ops::{Deref, DerefMut};
-use std::fmt::{Formatter, Debug, Error, Pointer};
-use std::cmp::Ordering;
+use alloc::rc::{Rc, Weak};
+use core::cell;
+use core::ops::{Deref, DerefMut};
+use core::fmt::{Formatter, Debug, Display, Error, Pointer};
+use core::any::Any;
+use core::error;
+use alloc::boxed::Box;
+use core::ptr;
+use core::ffi::c_void;
+#[cfg(feature = "track-borrows")]
+use core::panic::Location;
+use alloc::vec::Vec;
+use borrow_tracking::BorrowToken;
+
+pub use core::cell::BorrowError;
 
 /// A smart container for objects in recursive data structures
 ///
@@ -10,28 +20,316 @@ use std::cmp::Ordering;
 #[derive(Default)]
 pub struct SCell<T: ?Sized>(Rc<cell::RefCell<T>>);
 
+/// A weak reference to the contents of an `SCell`
+///
+/// This does not keep the contents alive, so it must be upgraded to an `SCell` before it can be borrowed.
+pub struct SWeak<T: ?Sized>(Weak<cell::RefCell<T>>);
+
 /// A reference wrapper that lets rust make the same guarantees regardless of internal type
-pub struct Ref<'a, T: 'a + ?Sized>(cell::Ref<'a, T>);
+pub struct Ref<'a, T: 'a + ?Sized>(cell::Ref<'a, T>, BorrowToken);
 
 /// A mutable reference wrapper that lets rust make the same guarantees regardless of internal type
-pub struct RefMut<'a, T: 'a + ?Sized>(cell::RefMut<'a, T>);
+pub struct RefMut<'a, T: 'a + ?Sized>(cell::RefMut<'a, T>, BorrowToken);
+
+/// An error returned by `SCell::try_borrow_mut`
+///
+/// With the `track-borrows` feature, this records where the borrow was attempted and where the outstanding borrows
+/// of the contents are held, and its `Display` output includes both.
+#[derive(Debug)]
+pub struct BorrowConflict {
+    #[cfg(feature = "track-borrows")]
+    attempted: &'static Location<'static>,
+    #[cfg(feature = "track-borrows")]
+    held: Vec<&'static Location<'static>>,
+    #[cfg(not(feature = "track-borrows"))]
+    _private: (),
+}
+
+/// The error returned by `SCell::try_borrow_mut`, under the name the unchecked backend also uses
+pub type BorrowMutError = BorrowConflict;
 
 impl<T> SCell<T> {
     #[inline]
     pub fn new(t: T) -> Self {
         SCell(Rc::new(cell::RefCell::new(t)))
     }
+
+    /// Constructs a new `SCell` from the value `f` returns, giving it an `SWeak` to the instance being constructed
+    ///
+    /// The `SWeak` can't be upgraded until this returns, but it can be stored in the value to build cycles.
+    #[inline]
+    pub fn new_cyclic<F>(f: F) -> Self
+        where F: FnOnce(&SWeak<T>) -> T
+    {
+        SCell(Rc::new_cyclic(|weak| cell::RefCell::new(f(&SWeak(weak.clone())))))
+    }
+
+    /// Replaces the contents with `t` and returns the old value
+    ///
+    /// Panics if the contents are currently borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn replace(&self, t: T) -> T {
+        self.0.replace(t)
+    }
+
+    /// Replaces the contents with the value computed by `f` and returns the old value
+    ///
+    /// Panics if the contents are currently borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn replace_with<F>(&self, f: F) -> T
+        where F: FnOnce(&mut T) -> T
+    {
+        self.0.replace_with(f)
+    }
+
+    /// Swaps the contents of two `SCell` instances
+    ///
+    /// Swapping an instance with itself does nothing, even while it is borrowed, like with the unchecked backend.
+    /// Otherwise this panics if either is currently borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn swap(&self, other: &Self) {
+        if !self.ptr_eq(other) {
+            self.0.swap(&other.0)
+        }
+    }
+
+    /// Returns the contents if this is the only `SCell` handle to them, otherwise returns the handle
+    #[inline]
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        Rc::try_unwrap(this.0).map(cell::RefCell::into_inner).map_err(SCell)
+    }
+
+    /// Returns the contents, consuming this handle
+    ///
+    /// Panics with the current strong count if there are other `SCell` handles to the contents.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        match Rc::try_unwrap(self.0) {
+            Ok(cell) => cell.into_inner(),
+            Err(rc) => panic!("cannot take the contents of an SCell with {} strong handles", Rc::strong_count(&rc)),
+        }
+    }
+
+    /// Unwraps this handle into the `Rc<RefCell<T>>` it is built on
+    ///
+    /// This never fails with the checked backend, but the unchecked backend has to reallocate the contents, so it gives
+    /// the handle back there if there are other handles to them.
+    #[inline]
+    pub fn try_into_rc(self) -> Result<Rc<cell::RefCell<T>>, Self> {
+        Ok(self.0)
+    }
+
+    /// Consumes the handle, returning an opaque pointer that keeps the instance alive until it is passed to `from_raw`
+    ///
+    /// The pointer identifies the allocation, which starts with the cell's bookkeeping rather than the contents, so it
+    /// is a `c_void` that can't be dereferenced. Use `as_ptr` to reach the contents instead. The pointer is thin, so it
+    /// can't carry the length or vtable that unsized contents would need.
+    #[inline]
+    pub fn into_raw(this: Self) -> *const c_void {
+        Rc::into_raw(this.0) as *const c_void
+    }
+
+    /// Reconstructs a handle from a pointer returned by `into_raw`
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `into_raw` on an `SCell<T>` of the same `T` built with the same backend, and
+    /// each pointer returned by `into_raw` must be passed to this exactly once. Passing it more often drops the
+    /// instance while other handles still refer to it, and never passing it leaks the instance.
+    #[inline]
+    pub unsafe fn from_raw(ptr: *const c_void) -> Self {
+        SCell(Rc::from_raw(ptr as *const cell::RefCell<T>))
+    }
 }
 
 impl<T: ?Sized> SCell<T> {
+    /// Borrows the contents
+    ///
+    /// Panics if the contents are mutably borrowed. With the `track-borrows` feature, the panic message includes where
+    /// the outstanding borrow was taken, and the hook set with `set_borrow_panic_hook` runs first.
     #[inline]
-    pub fn borrow(&self) -> Ref<T> {
-        Ref(self.0.borrow())
+    #[track_caller]
+    pub fn borrow(&self) -> Ref<'_, T> {
+        #[cfg(feature = "track-borrows")]
+        match self.try_borrow() {
+            Ok(r) => r,
+            Err(_) => ::borrow_tracking::conflict(self.as_ptr() as *const (), ::BorrowKind::Shared),
+        }
+        #[cfg(not(feature = "track-borrows"))]
+        Ref(self.0.borrow(), self.token())
     }
 
+    /// Mutably borrows the contents
+    ///
+    /// Panics if the contents are borrowed. With the `track-borrows` feature, the panic message includes where the
+    /// outstanding borrows were taken, and the hook set with `set_borrow_panic_hook` runs first.
     #[inline]
-    pub fn borrow_mut(&self) -> RefMut<T> {
-        RefMut(self.0.borrow_mut())
+    #[track_caller]
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        #[cfg(feature = "track-borrows")]
+        match self.try_borrow_mut() {
+            Ok(r) => r,
+            Err(_) => ::borrow_tracking::conflict(self.as_ptr() as *const (), ::BorrowKind::Mutable),
+        }
+        #[cfg(not(feature = "track-borrows"))]
+        RefMut(self.0.borrow_mut(), self.token())
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
+        match self.0.try_borrow() {
+            Ok(r) => Ok(Ref(r, self.token())),
+            Err(e) => Err(e),
+        }
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, BorrowMutError> {
+        match self.0.try_borrow_mut() {
+            Ok(r) => Ok(RefMut(r, self.token())),
+            Err(_) => Err(BorrowConflict::new(self.as_ptr() as *const ())),
+        }
+    }
+
+    #[inline]
+    pub fn downgrade(&self) -> SWeak<T> {
+        SWeak(Rc::downgrade(&self.0))
+    }
+
+    /// Gets the number of `SCell` handles to this instance without borrowing the contents
+    #[inline]
+    pub fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+
+    /// Gets the number of `SWeak` handles to this instance without borrowing the contents
+    #[inline]
+    pub fn weak_count(&self) -> usize {
+        Rc::weak_count(&self.0)
+    }
+
+    /// Gets the number of `SCell` and `SWeak` handles to this instance, in that order, without borrowing the contents
+    #[inline]
+    pub fn ref_counts(&self) -> (usize, usize) {
+        (Rc::strong_count(&self.0), Rc::weak_count(&self.0))
+    }
+
+    /// Checks if two `SCell` handles refer to the same instance without borrowing the contents
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Mutably borrows the contents without any runtime checks if this is the only handle to them
+    ///
+    /// Returns `None` if there are other `SCell` or `SWeak` handles to the contents.
+    #[inline]
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        Rc::get_mut(&mut self.0).map(cell::RefCell::get_mut)
+    }
+
+    /// Gets how this instance has been borrowed since it was created
+    ///
+    /// Only guards count, so checking whether the contents are borrowed or hashing them doesn't change the stats. The
+    /// stats are kept in a thread-local table keyed by the address of the contents rather than in the instance, so that
+    /// the feature doesn't change the layout that the conversions to and from `Rc<RefCell<T>>` rely on.
+    #[cfg(feature = "borrow-stats")]
+    #[inline]
+    pub fn borrow_stats(&self) -> ::BorrowStats {
+        ::borrow_tracking::stats(self.as_ptr() as *const ())
+    }
+
+    /// Borrows the contents without a guard, for checks that don't hand the borrow out and so shouldn't count as one
+    #[inline]
+    pub(crate) fn try_peek(&self) -> Result<cell::Ref<'_, T>, BorrowError> {
+        self.0.try_borrow()
+    }
+
+    /// Gets how the contents are borrowed right now without a guard, so the check doesn't count as a borrow
+    #[inline]
+    pub(crate) fn borrow_state(&self) -> ::BorrowState {
+        if self.0.try_borrow_mut().is_ok() {
+            ::BorrowState::Unused
+        } else if self.0.try_borrow().is_ok() {
+            ::BorrowState::Shared
+        } else {
+            ::BorrowState::Mutable
+        }
+    }
+
+    /// Registers a new guard of this instance taken by the caller, making room for the instance's stats first
+    #[inline]
+    #[track_caller]
+    fn token(&self) -> BorrowToken {
+        #[cfg(feature = "borrow-stats")]
+        ::borrow_tracking::register(self.as_ptr() as *const (), &self.0);
+        BorrowToken::new(self.as_ptr() as *const ())
+    }
+
+    /// Identifies this instance to the tasks waiting in `borrow_mut_async`
+    #[cfg(feature = "async")]
+    #[inline]
+    pub(crate) fn wait_key(&self) -> *const () {
+        self.as_ptr() as *const ()
+    }
+
+    /// Gets a raw pointer to the contents without borrowing them
+    ///
+    /// Dereferencing the pointer bypasses the borrow rules this type otherwise upholds, so it is up to the caller to
+    /// ensure it isn't used to alias a live `Ref` or `RefMut`.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut T {
+        self.0.as_ptr()
+    }
+}
+
+impl<T> SCell<[T]> {
+    /// Moves the elements of `v` into a new `SCell` holding a slice
+    ///
+    /// The length is fixed once the instance is built, since it is stored in each handle rather than in the instance,
+    /// so use an `SCell<Vec<T>>` for contents that need to grow or shrink. The elements are moved into a `Box` that is
+    /// then moved into the `Rc`, so this costs two copies of the `Vec`'s contents.
+    pub fn from_vec(v: Vec<T>) -> Self {
+        let len = v.len();
+        let empty: &cell::RefCell<[T]> = &cell::RefCell::new([]);
+        unsafe {
+            let start = ::alloc_with_tail(empty, empty.as_ptr() as *const T, v);
+            SCell(Rc::from(Box::from_raw(ptr::slice_from_raw_parts_mut(start, len) as *mut cell::RefCell<[T]>)))
+        }
+    }
+}
+
+impl SCell<dyn Any> {
+    /// Attempts to downcast the contents to a concrete type, returning a handle to the same instance on success
+    ///
+    /// Checking the type borrows the contents, so this panics if they are currently mutably borrowed.
+    #[inline]
+    pub fn downcast<U: Any>(self) -> Result<SCell<U>, Self> {
+        if self.borrow().is::<U>() {
+            let ptr = Rc::into_raw(self.0) as *const cell::RefCell<U>;
+            Ok(SCell(unsafe{Rc::from_raw(ptr)}))
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl<T> SWeak<T> {
+    #[inline]
+    pub fn new() -> Self {
+        SWeak(Weak::new())
+    }
+}
+
+impl<T: ?Sized> SWeak<T> {
+    #[inline]
+    pub fn upgrade(&self) -> Option<SCell<T>> {
+        self.0.upgrade().map(SCell)
     }
 }
 
@@ -42,106 +340,153 @@ impl<T: ?Sized> Clone for SCell<T> {
     }
 }
 
-impl<T: ?Sized> Pointer for SCell<T> {
+impl<T: ?Sized> Clone for SWeak<T> {
     #[inline]
-    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        self.0.fmt(f)
+    fn clone(&self) -> Self {
+        SWeak(self.0.clone())
     }
 }
 
-impl<'a, T: 'a + ?Sized> Deref for Ref<'a, T> {
-    type Target = T;
+impl<T> Default for SWeak<T> {
+    #[inline]
+    fn default() -> Self {
+        SWeak::new()
+    }
+}
 
+impl<T: ?Sized> From<Rc<cell::RefCell<T>>> for SCell<T> {
     #[inline]
-    fn deref(&self) -> &T {
-        &*self.0
+    fn from(rc: Rc<cell::RefCell<T>>) -> Self {
+        SCell(rc)
     }
 }
 
-impl<'a, T: 'a + ?Sized> Debug for Ref<'a, T>
-    where T: Debug
-{
+impl<T: ?Sized> From<SCell<T>> for Rc<cell::RefCell<T>> {
+    #[inline]
+    fn from(cell: SCell<T>) -> Self {
+        cell.0
+    }
+}
+
+impl<T: ?Sized> Pointer for SCell<T> {
     #[inline]
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        (*self.0).fmt(f)
+        Pointer::fmt(&self.0, f)
     }
 }
 
-impl<T: ?Sized> PartialEq for SCell<T>
-where T: PartialEq
-{
+impl<T: ?Sized> Pointer for SWeak<T> {
     #[inline]
-    fn eq(&self, other: &Self) -> bool {
-        if self.0.as_ptr() == other.0.as_ptr() {
-            true
-        } else {
-            *self.borrow() == *other.borrow()
-        }
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        Pointer::fmt(&self.0.as_ptr(), f)
     }
 }
 
-impl<T: ?Sized> Eq for SCell<T> where T: Eq {}
+impl<'a, T: 'a + ?Sized> Ref<'a, T> {
+    /// Makes another `Ref` to the same data without borrowing the cell again
+    ///
+    /// This is an associated function rather than `Clone` so that it doesn't shadow `clone` on the contents.
+    #[inline]
+    #[track_caller]
+    #[allow(clippy::should_implement_trait)]
+    pub fn clone(orig: &Self) -> Self {
+        Ref(cell::Ref::clone(&orig.0), orig.1.reborrow())
+    }
 
-impl<T: ?Sized> PartialOrd for SCell<T>
-where T: PartialOrd
-{
+    /// Makes a new `Ref` for a component of the borrowed data
+    ///
+    /// This is an associated function so that it doesn't shadow a method on the contents.
     #[inline]
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.0.as_ptr() == other.0.as_ptr() {
-            Some(Ordering::Equal)
-        } else {
-            self.borrow().partial_cmp(&*other.borrow())
-        }
+    pub fn map<U: ?Sized, F>(orig: Self, f: F) -> Ref<'a, U>
+        where F: FnOnce(&T) -> &U
+    {
+        Ref(cell::Ref::map(orig.0, f), orig.1)
     }
 
+    /// Makes a new `Ref` for an optional component of the borrowed data, or returns the original if there is none
     #[inline]
-    fn lt(&self, other: &Self) -> bool {
-        if self.0.as_ptr() == other.0.as_ptr() {
-            false
-        } else {
-            *self.borrow() < *other.borrow()
+    pub fn filter_map<U: ?Sized, F>(orig: Self, f: F) -> Result<Ref<'a, U>, Self>
+        where F: FnOnce(&T) -> Option<&U>
+    {
+        let Ref(r, token) = orig;
+        match cell::Ref::filter_map(r, f) {
+            Ok(u) => Ok(Ref(u, token)),
+            Err(r) => Err(Ref(r, token)),
         }
     }
+}
+
+impl<'a, T: 'a + ?Sized> Deref for Ref<'a, T> {
+    type Target = T;
 
     #[inline]
-    fn le(&self, other: &Self) -> bool {
-        if self.0.as_ptr() == other.0.as_ptr() {
-            true
-        } else {
-            *self.borrow() <= *other.borrow()
-        }
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Debug for Ref<'a, T>
+    where T: Debug
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        (*self.0).fmt(f)
     }
+}
 
+impl<'a, T: 'a + ?Sized> RefMut<'a, T> {
+    /// Makes a new `RefMut` for a component of the borrowed data
+    ///
+    /// This is an associated function so that it doesn't shadow a method on the contents.
     #[inline]
-    fn gt(&self, other: &Self) -> bool {
-        if self.0.as_ptr() == other.0.as_ptr() {
-            false
-        } else {
-            *self.borrow() > *other.borrow()
-        }
+    pub fn map<U: ?Sized, F>(orig: Self, f: F) -> RefMut<'a, U>
+        where F: FnOnce(&mut T) -> &mut U
+    {
+        RefMut(cell::RefMut::map(orig.0, f), orig.1)
     }
 
+    /// Makes a new `RefMut` for an optional component of the borrowed data, or returns the original if there is none
     #[inline]
-    fn ge(&self, other: &Self) -> bool {
-        if self.0.as_ptr() == other.0.as_ptr() {
-            true
-        } else {
-            *self.borrow() >= *other.borrow()
+    pub fn filter_map<U: ?Sized, F>(orig: Self, f: F) -> Result<RefMut<'a, U>, Self>
+        where F: FnOnce(&mut T) -> Option<&mut U>
+    {
+        let RefMut(r, token) = orig;
+        match cell::RefMut::filter_map(r, f) {
+            Ok(u) => Ok(RefMut(u, token)),
+            Err(r) => Err(RefMut(r, token)),
         }
     }
-}
 
-impl<T: ?Sized> Ord for SCell<T>
-where T: Ord
-{
+    /// Makes a new `RefMut` for a component of the borrowed data, or returns the original along with the error if `f`
+    /// fails
     #[inline]
-    fn cmp(&self, other: &Self) -> Ordering {
-        if self.0.as_ptr() == other.0.as_ptr() {
-            Ordering::Equal
-        } else {
-            self.borrow().cmp(&*other.borrow())
+    pub fn try_map<U: ?Sized, E, F>(orig: Self, f: F) -> Result<RefMut<'a, U>, (Self, E)>
+        where F: FnOnce(&mut T) -> Result<&mut U, E>
+    {
+        let RefMut(r, token) = orig;
+        let mut error = None;
+        let mapped = cell::RefMut::filter_map(r, |t| match f(t) {
+            Ok(u) => Some(u),
+            Err(e) => {
+                error = Some(e);
+                None
+            }
+        });
+        match mapped {
+            Ok(u) => Ok(RefMut(u, token)),
+            Err(r) => Err((RefMut(r, token), error.unwrap())),
         }
     }
+
+    /// Splits a `RefMut` into two for disjoint components of the borrowed data
+    #[inline]
+    pub fn map_split<U: ?Sized, V: ?Sized, F>(orig: Self, f: F) -> (RefMut<'a, U>, RefMut<'a, V>)
+        where F: FnOnce(&mut T) -> (&mut U, &mut V)
+    {
+        let RefMut(r, token) = orig;
+        let (u, v) = cell::RefMut::map_split(r, f);
+        (RefMut(u, token.duplicate()), RefMut(v, token))
+    }
 }
 
 impl<'a, T: 'a + ?Sized> Deref for RefMut<'a, T> {
@@ -149,14 +494,14 @@ impl<'a, T: 'a + ?Sized> Deref for RefMut<'a, T> {
 
     #[inline]
     fn deref(&self) -> &T {
-        &*self.0
+        &self.0
     }
 }
 
 impl<'a, T: 'a + ?Sized> DerefMut for RefMut<'a, T> {
     #[inline]
     fn deref_mut(&mut self) -> &mut T {
-        &mut *self.0
+        &mut self.0
     }
 }
 
@@ -168,3 +513,52 @@ impl<'a, T: 'a + ?Sized> Debug for RefMut<'a, T>
         (*self.0).fmt(f)
     }
 }
+
+impl BorrowConflict {
+    #[inline]
+    #[track_caller]
+    fn new(addr: *const ()) -> Self {
+        #[cfg(not(feature = "track-borrows"))]
+        let _ = addr;
+        BorrowConflict {
+            #[cfg(feature = "track-borrows")]
+            attempted: Location::caller(),
+            #[cfg(feature = "track-borrows")]
+            held: ::borrow_tracking::held(addr),
+            #[cfg(not(feature = "track-borrows"))]
+            _private: (),
+        }
+    }
+
+    /// Gets where the borrow that failed was attempted
+    #[cfg(feature = "track-borrows")]
+    #[inline]
+    pub fn attempted(&self) -> &'static Location<'static> {
+        self.attempted
+    }
+
+    /// Gets where the outstanding borrows that it conflicted with were taken
+    #[cfg(feature = "track-borrows")]
+    #[inline]
+    pub fn held(&self) -> &[&'static Location<'static>] {
+        &self.held
+    }
+}
+
+impl Display for BorrowConflict {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        #[cfg(feature = "track-borrows")]
+        {
+            if self.held.is_empty() {
+                write!(f, "borrow attempted at {} conflicts with an outstanding borrow", self.attempted)
+            } else {
+                write!(f, "borrow attempted at {} conflicts with borrows held at {}", self.attempted,
+                    ::borrow_tracking::Locations(&self.held))
+            }
+        }
+        #[cfg(not(feature = "track-borrows"))]
+        f.write_str("already borrowed")
+    }
+}
+
+impl error::Error for BorrowConflict {}
@@ -0,0 +1,213 @@
+//! Conveniences for `SCell`s holding standard collections.
+//!
+//! The `Extend` impls hold a mutable borrow of the contents for as long as the iterator runs, so the iterator must not
+//! borrow the instance being extended.
+
+use core::iter::FromIterator;
+#[cfg(feature = "std")]
+use core::hash::{BuildHasher, Hash};
+#[cfg(feature = "std")]
+use core::borrow::Borrow;
+use alloc::vec::{self, Vec};
+use alloc::string::String;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+use {ConvertError, SCell};
+
+impl<T> FromIterator<T> for SCell<Vec<T>> {
+    #[inline]
+    fn from_iter<I>(iter: I) -> Self
+        where I: IntoIterator<Item = T>
+    {
+        SCell::new(iter.into_iter().collect())
+    }
+}
+
+impl<T> SCell<Vec<T>> {
+    /// Consumes the contents as an owning iterator if this is the only `SCell` handle to them
+    #[inline]
+    pub fn into_iter_owned(self) -> Option<vec::IntoIter<T>> {
+        SCell::try_unwrap(self).ok().map(Vec::into_iter)
+    }
+
+    /// Removes every element, keeping the allocated capacity
+    #[inline]
+    #[track_caller]
+    pub fn clear(&self) {
+        self.borrow_mut().clear();
+    }
+
+    /// Reserves capacity for at least `additional` more elements, so that a bulk append doesn't reallocate as it goes
+    #[inline]
+    #[track_caller]
+    pub fn reserve(&self, additional: usize) {
+        self.borrow_mut().reserve(additional);
+    }
+
+    /// Shrinks the allocated capacity as close to the length as the allocator allows, such as after removing most of
+    /// the elements
+    #[inline]
+    #[track_caller]
+    pub fn shrink_to_fit(&self) {
+        self.borrow_mut().shrink_to_fit();
+    }
+
+    /// Moves the contents into a new `SCell` holding an array if this is the only handle to them
+    ///
+    /// If the length isn't `N`, the error holds the `Vec`.
+    #[inline]
+    #[allow(clippy::type_complexity)]
+    pub fn try_into_array<const N: usize>(self) -> Result<SCell<[T; N]>, ConvertError<Vec<T>, Vec<T>>> {
+        self.try_convert()
+    }
+}
+
+impl<T, const N: usize> SCell<[T; N]> {
+    /// Moves the contents into a new `SCell` holding a `Vec` if this is the only handle to them, otherwise returns
+    /// the handle
+    #[inline]
+    pub fn into_vec(self) -> Result<SCell<Vec<T>>, Self> {
+        SCell::try_unwrap(self).map(|array| SCell::new(Vec::from(array)))
+    }
+}
+
+impl<T> From<&[T]> for SCell<Vec<T>>
+    where T: Clone
+{
+    #[inline]
+    fn from(slice: &[T]) -> Self {
+        SCell::new(slice.to_vec())
+    }
+}
+
+/// Appends the items to the `Vec`
+impl<T> Extend<T> for SCell<Vec<T>> {
+    #[inline]
+    #[track_caller]
+    fn extend<I>(&mut self, iter: I)
+        where I: IntoIterator<Item = T>
+    {
+        self.borrow_mut().extend(iter)
+    }
+}
+
+/// Appends the items to the `Vec` through a shared handle
+impl<T> Extend<T> for &SCell<Vec<T>> {
+    #[inline]
+    #[track_caller]
+    fn extend<I>(&mut self, iter: I)
+        where I: IntoIterator<Item = T>
+    {
+        self.borrow_mut().extend(iter)
+    }
+}
+
+/// Each of these takes its own borrow, so no borrow is held across logging or formatting calls that read the buffer.
+impl SCell<String> {
+    #[inline]
+    #[track_caller]
+    pub fn push(&self, c: char) {
+        self.borrow_mut().push(c);
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn push_str(&self, s: &str) {
+        self.borrow_mut().push_str(s);
+    }
+
+    /// Removes the whole string, keeping the allocated capacity
+    #[inline]
+    #[track_caller]
+    pub fn clear(&self) {
+        self.borrow_mut().clear();
+    }
+
+    /// Reserves capacity for at least `additional` more bytes
+    #[inline]
+    #[track_caller]
+    pub fn reserve(&self, additional: usize) {
+        self.borrow_mut().reserve(additional);
+    }
+
+    /// Shrinks the allocated capacity as close to the length as the allocator allows, such as after removing most of
+    /// the text
+    #[inline]
+    #[track_caller]
+    pub fn shrink_to_fit(&self) {
+        self.borrow_mut().shrink_to_fit();
+    }
+}
+
+/// Appends the characters to the `String`
+impl Extend<char> for SCell<String> {
+    #[inline]
+    #[track_caller]
+    fn extend<I>(&mut self, iter: I)
+        where I: IntoIterator<Item = char>
+    {
+        self.borrow_mut().extend(iter)
+    }
+}
+
+/// Appends the strings to the `String`
+impl<'a> Extend<&'a str> for SCell<String> {
+    #[inline]
+    #[track_caller]
+    fn extend<I>(&mut self, iter: I)
+        where I: IntoIterator<Item = &'a str>
+    {
+        self.borrow_mut().extend(iter)
+    }
+}
+
+/// Appends the characters to the `String` through a shared handle
+impl Extend<char> for &SCell<String> {
+    #[inline]
+    #[track_caller]
+    fn extend<I>(&mut self, iter: I)
+        where I: IntoIterator<Item = char>
+    {
+        self.borrow_mut().extend(iter)
+    }
+}
+
+/// Appends the strings to the `String` through a shared handle
+impl<'a> Extend<&'a str> for &SCell<String> {
+    #[inline]
+    #[track_caller]
+    fn extend<I>(&mut self, iter: I)
+        where I: IntoIterator<Item = &'a str>
+    {
+        self.borrow_mut().extend(iter)
+    }
+}
+
+/// Each of these takes its own borrow, so no borrow is held while the caller does other lookups.
+#[cfg(feature = "std")]
+impl<K, V, S> SCell<HashMap<K, V, S>>
+    where K: Eq + Hash, S: BuildHasher
+{
+    #[inline]
+    #[track_caller]
+    pub fn insert(&self, k: K, v: V) -> Option<V> {
+        self.borrow_mut().insert(k, v)
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn get_cloned<Q>(&self, k: &Q) -> Option<V>
+        where K: Borrow<Q>, Q: ?Sized + Eq + Hash, V: Clone
+    {
+        self.borrow().get(k).cloned()
+    }
+
+    /// Runs `f` on the value for `k` if there is one
+    #[inline]
+    #[track_caller]
+    pub fn with_entry<Q, R, F>(&self, k: &Q, f: F) -> Option<R>
+        where K: Borrow<Q>, Q: ?Sized + Eq + Hash, F: FnOnce(&mut V) -> R
+    {
+        self.borrow_mut().get_mut(k).map(f)
+    }
+}
@@ -0,0 +1,131 @@
+use alloc::rc::Rc;
+use core::cell::Cell;
+use core::cmp::Ordering;
+use core::fmt::{Formatter, Display, Debug, Error, Pointer};
+
+/// A cheaper sibling of `SCell` for `Copy` contents, backed by a `Rc<Cell<T>>`
+///
+/// The contents are copied in and out with `get` and `set` rather than borrowed, so there are no guards and nothing to
+/// check at runtime in either backend. This suits small values, such as the weights in a graph.
+pub struct CopyCell<T: Copy>(Rc<Cell<T>>);
+
+impl<T: Copy> CopyCell<T> {
+    #[inline]
+    pub fn new(t: T) -> Self {
+        CopyCell(Rc::new(Cell::new(t)))
+    }
+
+    /// Returns a copy of the contents
+    #[inline]
+    pub fn get(&self) -> T {
+        self.0.get()
+    }
+
+    /// Sets the contents, which is seen by all handles to them
+    #[inline]
+    pub fn set(&self, t: T) {
+        self.0.set(t);
+    }
+
+    /// Sets the contents and returns the old contents
+    #[inline]
+    pub fn replace(&self, t: T) -> T {
+        self.0.replace(t)
+    }
+
+    /// Returns true if both handles point to the same instance
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Gets the underlying `Rc<Cell<T>>`
+    #[inline]
+    pub fn into_rc(self) -> Rc<Cell<T>> {
+        self.0
+    }
+}
+
+impl<T: Copy> Clone for CopyCell<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        CopyCell(self.0.clone())
+    }
+}
+
+impl<T: Copy> Default for CopyCell<T>
+    where T: Default
+{
+    #[inline]
+    fn default() -> Self {
+        CopyCell::new(T::default())
+    }
+}
+
+impl<T: Copy> From<T> for CopyCell<T> {
+    #[inline]
+    fn from(t: T) -> Self {
+        CopyCell::new(t)
+    }
+}
+
+impl<T: Copy> From<Rc<Cell<T>>> for CopyCell<T> {
+    #[inline]
+    fn from(rc: Rc<Cell<T>>) -> Self {
+        CopyCell(rc)
+    }
+}
+
+impl<T: Copy> PartialEq for CopyCell<T>
+    where T: PartialEq
+{
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+
+impl<T: Copy> Eq for CopyCell<T> where T: Eq {}
+
+impl<T: Copy> PartialOrd for CopyCell<T>
+    where T: PartialOrd
+{
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.get().partial_cmp(&other.get())
+    }
+}
+
+impl<T: Copy> Ord for CopyCell<T>
+    where T: Ord
+{
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.get().cmp(&other.get())
+    }
+}
+
+impl<T: Copy> Display for CopyCell<T>
+    where T: Display
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        self.get().fmt(f)
+    }
+}
+
+impl<T: Copy> Debug for CopyCell<T>
+    where T: Debug
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        self.get().fmt(f)
+    }
+}
+
+impl<T: Copy> Pointer for CopyCell<T> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        Pointer::fmt(&self.0, f)
+    }
+}
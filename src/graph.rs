@@ -0,0 +1,233 @@
+//! Utilities for building and walking graphs of `SCell`s, enabled by the `graph-utils` feature.
+//!
+//! The walks tell nodes apart by pointer identity rather than by their contents, and skip nodes that are mutably
+//! borrowed rather than panicking.
+
+// `PtrKey` hashes and compares by address, so the interior mutability of the cell it holds can't change its hash.
+#![allow(clippy::mutable_key_type)]
+
+use alloc::vec::{self, Vec};
+use std::collections::HashSet;
+use {BorrowState, PtrKey, SCell, SWeak};
+
+impl<T: ?Sized> SCell<T> {
+    /// Checks whether a cycle is reachable from this node by following the edges returned by `successors`
+    ///
+    /// A node that is mutably borrowed while this runs is treated as having no successors, so a cycle that passes
+    /// through it won't be found.
+    pub fn detect_cycle<F>(&self, successors: F) -> bool
+        where F: Fn(&T) -> Vec<SCell<T>>
+    {
+        let successors = |node: &SCell<T>| -> vec::IntoIter<SCell<T>> {
+            node.try_borrow().map(|node| successors(&node)).unwrap_or_default().into_iter()
+        };
+        let mut on_path = HashSet::new();
+        let mut finished = HashSet::new();
+        let mut stack = vec![(PtrKey(self.clone()), successors(self))];
+        on_path.insert(PtrKey(self.clone()));
+        loop {
+            let next = match stack.last_mut() {
+                Some(&mut (_, ref mut children)) => children.next(),
+                None => return false,
+            };
+            match next {
+                Some(child) => {
+                    let child = PtrKey(child);
+                    if on_path.contains(&child) {
+                        return true;
+                    }
+                    if !finished.contains(&child) {
+                        let children = successors(&child.0);
+                        on_path.insert(child.clone());
+                        stack.push((child, children));
+                    }
+                }
+                None => {
+                    let (node, _) = stack.pop().unwrap();
+                    on_path.remove(&node);
+                    finished.insert(node);
+                }
+            }
+        }
+    }
+
+    /// Folds `f` over every node reachable from `roots` by following the edges returned by `successors`
+    ///
+    /// Each node is visited once, in depth-first order, so cycles and shared nodes are fine. A node that is mutably
+    /// borrowed while this runs is either skipped along with the edges out of it, or ends the walk with an error
+    /// holding that node, depending on `on_borrowed`.
+    pub fn fold_graph<A, S, F>(roots: &[SCell<T>], init: A, on_borrowed: OnBorrowed, successors: S, mut f: F)
+        -> Result<A, SCell<T>>
+        where S: Fn(&T) -> Vec<SCell<T>>, F: FnMut(A, &T) -> A
+    {
+        let mut visited = HashSet::new();
+        let mut stack: Vec<SCell<T>> = roots.iter().rev().cloned().collect();
+        let mut acc = init;
+        while let Some(node) = stack.pop() {
+            if !visited.insert(PtrKey(node.clone())) {
+                continue;
+            }
+            let t = match node.try_borrow() {
+                Ok(t) => t,
+                Err(_) => match on_borrowed {
+                    OnBorrowed::Skip => continue,
+                    OnBorrowed::Fail => return Err(node.clone()),
+                },
+            };
+            stack.extend(successors(&t).into_iter().rev());
+            acc = f(acc, &t);
+        }
+        Ok(acc)
+    }
+
+    /// Checks that every node reachable from `roots` by following the edges returned by `successors` can be mutably
+    /// borrowed right now, or returns the first one that can't
+    ///
+    /// This is for checking up front that a mutating pass won't panic part way through. Each node's borrow flag is
+    /// read without counting as a borrow, and the contents are only borrowed to follow the edges out of nodes that
+    /// aren't borrowed at all, so the result is only good until the caller borrows something. The unchecked backend
+    /// only has a borrow flag when `debug_assertions` are enabled, so in release this always succeeds without walking.
+    pub fn check_all_borrowable_mut<F>(roots: &[SCell<T>], successors: F) -> Result<(), SCell<T>>
+        where F: Fn(&T) -> Vec<SCell<T>>
+    {
+        let mut visited = HashSet::new();
+        let mut stack = roots.to_vec();
+        while let Some(node) = stack.pop() {
+            if visited.insert(PtrKey(node.clone())) {
+                match node.borrow_state() {
+                    BorrowState::Unused => {}
+                    BorrowState::Shared | BorrowState::Mutable => return Err(node),
+                    // Following the edges would mean reading contents that may be mutably borrowed.
+                    BorrowState::Untracked => return Ok(()),
+                }
+                if let Ok(t) = node.try_peek() {
+                    stack.extend(successors(&t));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> SCell<T> {
+    /// Walks the graph reachable from `roots` and unwraps every node that the walk ends up holding the last strong
+    /// handle to
+    ///
+    /// Nodes are visited once each, so cycles are fine. Handles that are left inside the contents keep nodes alive, so
+    /// for a teardown `successors` should move the edges out of each node, such as with `SCell::take` on an
+    /// `SCell<Vec<SCell<T>>>` field, rather than cloning them. Nodes that are also held from outside of the graph are
+    /// left alone, and that includes the roots, since the caller still holds them. A node that is mutably borrowed
+    /// while this runs is treated as having no successors.
+    pub fn drain_unique<F>(roots: &[SCell<T>], successors: F) -> Vec<T>
+        where F: Fn(&T) -> Vec<SCell<T>>
+    {
+        let mut visited = HashSet::new();
+        let mut nodes = Vec::new();
+        let mut stack = roots.to_vec();
+        while let Some(node) = stack.pop() {
+            if visited.insert(PtrKey(node.clone())) {
+                if let Ok(t) = node.try_borrow() {
+                    stack.extend(successors(&t));
+                }
+                nodes.push(node);
+            }
+        }
+        // The visited set holds a second handle to every node, which would stop any of them from being unwrapped.
+        drop(visited);
+        nodes.into_iter().filter_map(|node| SCell::try_unwrap(node).ok()).collect()
+    }
+}
+
+/// What `SCell::fold_graph` does when it reaches a node that is mutably borrowed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnBorrowed {
+    /// Leave the node and the edges out of it out of the walk
+    Skip,
+    /// End the walk, returning the node as the error
+    Fail,
+}
+
+/// A node that can have children wired up by a `GraphBuilder`
+pub trait GraphNode: Sized {
+    /// Adds a strong handle to a child of this node
+    fn add_child(&mut self, child: SCell<Self>);
+
+    /// Gives this node a weak handle to one of its parents
+    ///
+    /// This does nothing by default, for nodes that don't link back to their parents.
+    #[inline]
+    fn set_parent(&mut self, parent: SWeak<Self>) {
+        let _ = parent;
+    }
+}
+
+/// The index of a node added to a `GraphBuilder`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// Gets the index of the node in the `Vec` returned by `GraphBuilder::build`
+    #[inline]
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Collects nodes and the links between them, then builds them into `SCell`s all at once
+///
+/// Each parent holds a strong handle to each of its children, and each child holds a weak handle back to its parent,
+/// so dropping the roots drops the whole graph unless the parent-to-child links form a cycle.
+pub struct GraphBuilder<T> {
+    nodes: Vec<T>,
+    links: Vec<(NodeId, NodeId)>,
+}
+
+impl<T> GraphBuilder<T>
+    where T: GraphNode
+{
+    #[inline]
+    pub fn new() -> Self {
+        GraphBuilder {
+            nodes: Vec::new(),
+            links: Vec::new(),
+        }
+    }
+
+    /// Adds a node to the graph
+    #[inline]
+    pub fn add(&mut self, value: T) -> NodeId {
+        self.nodes.push(value);
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Makes `child` a child of `parent` once the graph is built
+    ///
+    /// Panics if either is out of range for this builder. A `NodeId` doesn't remember which builder returned it, so one
+    /// from another builder that happens to be in range links whichever node was added here at that index.
+    #[inline]
+    pub fn link(&mut self, parent: NodeId, child: NodeId) {
+        assert!(parent.0 < self.nodes.len() && child.0 < self.nodes.len(), "node is not in this graph");
+        self.links.push((parent, child));
+    }
+
+    /// Builds every node into an `SCell` and links them in the order `link` was called, returning them in the order
+    /// they were added
+    pub fn build(self) -> Vec<SCell<T>> {
+        let cells: Vec<SCell<T>> = self.nodes.into_iter().map(SCell::new).collect();
+        for (parent, child) in self.links {
+            let (parent, child) = (&cells[parent.0], &cells[child.0]);
+            parent.borrow_mut().add_child(child.clone());
+            child.borrow_mut().set_parent(parent.downgrade());
+        }
+        cells
+    }
+}
+
+impl<T> Default for GraphBuilder<T>
+    where T: GraphNode
+{
+    #[inline]
+    fn default() -> Self {
+        GraphBuilder::new()
+    }
+}
@@ -0,0 +1,56 @@
+use alloc::vec::Vec;
+use core::iter::FusedIterator;
+use core::slice;
+use SCell;
+
+/// An iterator that clones each handle in a slice of `SCell`s as it is reached, returned by `SCell::clone_handles`
+///
+/// The clones share their allocations with the originals, so this only touches the reference counts.
+#[derive(Debug)]
+pub struct CloneHandles<'a, T: 'a + ?Sized>(slice::Iter<'a, SCell<T>>);
+
+impl<T: ?Sized> SCell<T> {
+    /// Clones every handle in `cells`
+    #[inline]
+    pub fn clone_all(cells: &[SCell<T>]) -> Vec<SCell<T>> {
+        cells.to_vec()
+    }
+
+    /// Iterates over clones of the handles in `cells`, only cloning each one when it is reached
+    #[inline]
+    pub fn clone_handles(cells: &[SCell<T>]) -> CloneHandles<'_, T> {
+        CloneHandles(cells.iter())
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Clone for CloneHandles<'a, T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        CloneHandles(self.0.clone())
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Iterator for CloneHandles<'a, T> {
+    type Item = SCell<T>;
+
+    #[inline]
+    fn next(&mut self) -> Option<SCell<T>> {
+        self.0.next().cloned()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, T: 'a + ?Sized> DoubleEndedIterator for CloneHandles<'a, T> {
+    #[inline]
+    fn next_back(&mut self) -> Option<SCell<T>> {
+        self.0.next_back().cloned()
+    }
+}
+
+impl<'a, T: 'a + ?Sized> ExactSizeIterator for CloneHandles<'a, T> {}
+
+impl<'a, T: 'a + ?Sized> FusedIterator for CloneHandles<'a, T> {}
@@ -0,0 +1,27 @@
+//! Each call takes its own mutable borrow, so no borrow is held between calls.
+
+use std::io::{self, Read, Write};
+use SCell;
+
+impl<T: ?Sized> Read for SCell<T>
+    where T: Read
+{
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.borrow_mut().read(buf)
+    }
+}
+
+impl<T: ?Sized> Write for SCell<T>
+    where T: Write
+{
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.borrow_mut().write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.borrow_mut().flush()
+    }
+}
@@ -0,0 +1,49 @@
+//! `Sum` and `Product` for the primitive number types over iterators of `SCell`s.
+//!
+//! These can't be implemented for every `T: Sum` because of the orphan rules, so they are implemented for each
+//! primitive instead. Each cell is only borrowed while its value is read, so a cell can appear more than once.
+
+use core::iter::{Sum, Product};
+use SCell;
+
+macro_rules! impl_sum_product {
+    ($($t:ty)*) => {$(
+        impl Sum<SCell<$t>> for $t {
+            #[inline]
+            fn sum<I>(iter: I) -> Self
+                where I: Iterator<Item = SCell<$t>>
+            {
+                iter.map(|cell| *cell.borrow()).sum()
+            }
+        }
+
+        impl<'a> Sum<&'a SCell<$t>> for $t {
+            #[inline]
+            fn sum<I>(iter: I) -> Self
+                where I: Iterator<Item = &'a SCell<$t>>
+            {
+                iter.map(|cell| *cell.borrow()).sum()
+            }
+        }
+
+        impl Product<SCell<$t>> for $t {
+            #[inline]
+            fn product<I>(iter: I) -> Self
+                where I: Iterator<Item = SCell<$t>>
+            {
+                iter.map(|cell| *cell.borrow()).product()
+            }
+        }
+
+        impl<'a> Product<&'a SCell<$t>> for $t {
+            #[inline]
+            fn product<I>(iter: I) -> Self
+                where I: Iterator<Item = &'a SCell<$t>>
+            {
+                iter.map(|cell| *cell.borrow()).product()
+            }
+        }
+    )*};
+}
+
+impl_sum_product!(i8 i16 i32 i64 i128 isize u8 u16 u32 u64 u128 usize f32 f64);
@@ -0,0 +1,62 @@
+use core::cell::{Cell, OnceCell};
+use core::ops::Deref;
+use core::fmt::{Formatter, Debug, Error};
+use SCell;
+
+/// An `SCell` that is constructed on first access
+///
+/// `SCell::new` allocates, so it can't be called in a `const` context such as a `const` initializer in
+/// `thread_local!`. This defers construction to the first time it is dereferenced and can itself be constructed in a
+/// `const` context.
+pub struct LazySCell<T, F = fn() -> T> {
+    cell: OnceCell<SCell<T>>,
+    init: Cell<Option<F>>,
+}
+
+impl<T, F> LazySCell<T, F> {
+    /// Creates a lazy cell that will construct its `SCell` from the value returned by `init`
+    #[inline]
+    pub const fn new(init: F) -> Self {
+        LazySCell {
+            cell: OnceCell::new(),
+            init: Cell::new(Some(init)),
+        }
+    }
+}
+
+impl<T, F> LazySCell<T, F>
+    where F: FnOnce() -> T
+{
+    /// Constructs the `SCell` if that hasn't happened yet and returns it
+    ///
+    /// Panics if a previous call to the initializer panicked.
+    #[inline]
+    pub fn force(this: &Self) -> &SCell<T> {
+        this.cell.get_or_init(|| match this.init.take() {
+            Some(init) => SCell::new(init()),
+            None => panic!("LazySCell initializer previously panicked"),
+        })
+    }
+}
+
+impl<T, F> Deref for LazySCell<T, F>
+    where F: FnOnce() -> T
+{
+    type Target = SCell<T>;
+
+    #[inline]
+    fn deref(&self) -> &SCell<T> {
+        LazySCell::force(self)
+    }
+}
+
+impl<T, F> Debug for LazySCell<T, F>
+    where T: Debug
+{
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self.cell.get() {
+            Some(cell) => f.debug_tuple("LazySCell").field(cell).finish(),
+            None => f.write_str("LazySCell(<uninit>)"),
+        }
+    }
+}
@@ -13,8 +13,58 @@
 //! Alternatively, feel free to use this crate for normal use in graphs, meshes, and other recurrent data structures
 //! with lots of interconnectivity where the borrow checker simply can't help. Later, if your code works fine and you
 //! need the performance back from `RefCell`, just use the `unchecked` feature and your code will be good to go.
+//!
+//! Coercing an `SCell<T>` into an `SCell<U>` where `T: Unsize<U>`, such as to make an `SCell<dyn Trait>`, relies on the
+//! unstable `CoerceUnsized` trait, so it is only available with the `nightly` feature on a nightly compiler.
+//!
+//! `SCell` itself only needs `alloc`, so disabling the default `std` feature makes this crate `no_std`.
+//!
+//! The `force-send` feature makes the `unchecked` backend's `SCell` `Send` and `Sync` for runtimes that move data
+//! between threads but never touch it from two threads at once. This is extremely unsafe, since nothing is atomic, and
+//! it is a compile error without `unchecked`.
+//!
+//! The `auto-deref` feature makes the `unchecked` backend's `SCell` dereference straight to its contents, so `*cell`
+//! reads without calling `borrow`. A checked `SCell` could only do that by leaking a borrow that would stop it from
+//! ever being mutated again, so this is a compile error without `unchecked`.
+//!
+//! The `tracked` feature adds `SCell::new_tracked` and `SCell::live_handles`, which keep a thread-local registry of
+//! allocations so that the live ones can be listed when tracking down leaks. This is slow, so it is only for debugging.
+//!
+//! The `track-borrows` feature makes the checked backend record where each outstanding borrow was taken, so that the
+//! panic from a conflicting `borrow` or `borrow_mut` says where the borrow it conflicts with is held. It also adds
+//! `set_borrow_panic_hook`, which registers a function that is passed those details right before that panic. With
+//! `unchecked` these still exist so that code builds with either backend, but the hook is never called.
+//!
+//! The `async` feature adds `SCell::borrow_mut_async`, which waits for the outstanding borrows to be dropped without
+//! blocking the thread, for single-threaded executors that share state between tasks. With `unchecked` it only waits in
+//! debug builds, since release builds don't track borrows.
+//!
+//! The `borrow-stats` feature adds `SCell::borrow_stats`, which reports how often an instance has been borrowed and
+//! the most borrows it has had at once, for finding contended cells. It only applies to the checked backend, so with
+//! `unchecked` the stats are always empty.
+//!
+//! The `graph-utils` feature adds helpers for walking graphs of `SCell`s, such as `SCell::detect_cycle`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "nightly", feature(coerce_unsized, unsize))]
+#[cfg(feature = "std")]
+extern crate core;
+extern crate alloc;
+#[cfg(feature = "serde")]
+extern crate serde;
+
+#[cfg(all(feature = "force-send", not(feature = "unchecked")))]
+compile_error!("the `force-send` feature only applies to the `unchecked` backend, so it requires `unchecked`");
+
+#[cfg(all(feature = "auto-deref", not(feature = "unchecked")))]
+compile_error!("the `auto-deref` feature only applies to the `unchecked` backend, so it requires `unchecked`");
 
-#![feature(coerce_unsized, unsize)]
+#[cfg(not(feature = "unchecked"))]
+mod borrow_tracking;
+#[cfg(all(feature = "borrow-stats", not(feature = "unchecked")))]
+pub use borrow_tracking::BorrowStats;
+#[cfg(all(feature = "track-borrows", not(feature = "unchecked")))]
+pub use borrow_tracking::{BorrowFailure, BorrowKind, set_borrow_panic_hook};
 #[cfg(not(feature = "unchecked"))]
 mod checked;
 #[cfg(not(feature = "unchecked"))]
@@ -25,13 +75,136 @@ mod unchecked;
 #[cfg(feature = "unchecked")]
 pub use unchecked::*;
 
-use std::fmt::{Formatter, Display, Debug, Error};
-use std::hash::{Hasher, Hash};
-use std::ops::CoerceUnsized;
-use std::marker::Unsize;
+#[cfg(feature = "sync")]
+mod sync;
+#[cfg(feature = "sync")]
+pub use sync::*;
+
+#[cfg(feature = "serde")]
+mod serde_impls;
+
+mod collections;
+
+mod iter_impls;
+
+mod parented;
+pub use parented::Parented;
+
+mod lazy;
+pub use lazy::LazySCell;
+
+mod copy_cell;
+pub use copy_cell::CopyCell;
+
+mod handles;
+pub use handles::CloneHandles;
+
+#[cfg(feature = "std")]
+mod io_impls;
+
+#[cfg(feature = "graph-utils")]
+mod graph;
+#[cfg(feature = "graph-utils")]
+pub use graph::{GraphBuilder, GraphNode, NodeId, OnBorrowed};
+
+#[cfg(feature = "tracked")]
+mod tracked;
+
+#[cfg(feature = "async")]
+mod waiters;
+#[cfg(feature = "async")]
+pub use waiters::BorrowMutFuture;
+
+use core::fmt::{Formatter, Display, Debug, Error, Pointer, LowerHex, UpperHex, Binary, Octal, LowerExp, UpperExp};
+use core::hash::{Hasher, Hash};
+use core::pin::Pin;
+use core::cmp::Ordering;
+use core::mem;
+use core::convert::TryFrom;
+use core::error;
+use alloc::rc::Rc;
+use alloc::boxed::Box;
+use alloc::borrow::ToOwned;
+use alloc::vec::Vec;
+use alloc::alloc::Layout;
+use core::ptr;
+use core::ops::{Deref, DerefMut, Index, IndexMut, Add, Sub, Mul, Div, Neg};
+#[cfg(feature = "nightly")]
+use core::ops::CoerceUnsized;
+#[cfg(feature = "nightly")]
+use core::marker::Unsize;
+
+/// A wrapper that hashes and compares an `SCell` by the identity of its instance rather than by its contents
+///
+/// Unlike the `Hash` and `Eq` impls on `SCell`, these never borrow the contents, so a key stays put in a `HashSet`
+/// even while its contents are mutably borrowed or modified. This makes it suitable for sets of visited nodes.
+pub struct PtrKey<T: ?Sized>(pub SCell<T>);
+
+/// A wrapper that orders an `SCell` by the address of its instance rather than by its contents
+///
+/// Unlike the `Ord` impl on `SCell`, this never borrows the contents and doesn't change when they are mutated, so it
+/// is suitable for keys of a `BTreeMap` or `BTreeSet` of nodes. The order is stable for as long as the instance is
+/// alive, but it is arbitrary and differs between runs.
+pub struct PtrOrd<T: ?Sized>(pub SCell<T>);
+
+/// Formats an `SCell` as its address and strong count, returned by `SCell::debug_shallow`
+struct DebugShallow<'a, T: 'a + ?Sized>(&'a SCell<T>);
+
+/// A guard marking an `SCell` as entered by a traversal for as long as it lives
+///
+/// This holds a mutable borrow of the contents, so entering the same instance again while it is alive fails, except in
+/// unchecked release builds, where doing so is undefined behavior.
+pub struct EnterGuard<'a, T: 'a + ?Sized>(RefMut<'a, T>);
+
+/// A guard keeping an `SCell` frozen for as long as it lives, returned by `SCell::freeze`
+///
+/// This holds a shared borrow of the contents, so they can still be read, but `borrow_mut` panics and `try_borrow_mut`
+/// fails until the guard is dropped.
+pub struct FreezeGuard<'a, T: 'a + ?Sized>(Ref<'a, T>);
+
+/// How the contents of an instance are borrowed right now, returned by each backend's `borrow_state`
+///
+/// Each backend only constructs the variants that its borrow flag, or the lack of one, can report.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+enum BorrowState {
+    /// Not borrowed at all
+    Unused,
+    /// Borrowed by at least one `Ref`
+    Shared,
+    /// Borrowed by a `RefMut`
+    Mutable,
+    /// Unknown, because the unchecked backend has no borrow flag in release
+    Untracked,
+}
+
+/// An error returned by `SCell::try_convert`
+#[derive(Debug)]
+pub enum ConvertError<T, E> {
+    /// There are other handles to the contents, so this handle is given back untouched
+    Shared(SCell<T>),
+    /// The conversion itself failed, and the contents were consumed by it
+    Failed(E),
+}
+
+/// An error returned by `SCell::swap_checked`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwapError {
+    /// Both handles refer to the same instance
+    Aliased,
+    /// One of the instances is already borrowed
+    Borrowed,
+}
 
+#[cfg(feature = "nightly")]
 impl<T, U> CoerceUnsized<SCell<U>> for SCell<T> where T: Unsize<U> + ?Sized, U: ?Sized {}
 
+/// Hashes the contents, or the address of the contents if they are mutably borrowed
+///
+/// This never panics, but a cell hashes differently while it is mutably borrowed than it does otherwise, so a hash
+/// taken during a mutation won't match one taken outside of it. The unchecked backend has no borrow flag in release,
+/// so it can't tell whether reading the contents would alias a `RefMut` and always hashes the address there. Two
+/// instances with equal contents then hash differently, so they don't work as keys that are looked up by contents.
 impl<T: ?Sized> Hash for SCell<T>
     where T: Hash
 {
@@ -39,12 +212,145 @@ impl<T: ?Sized> Hash for SCell<T>
     fn hash<H>(&self, state: &mut H)
         where H: Hasher
     {
-        self.borrow().hash(state);
+        let contents = match self.borrow_state() {
+            BorrowState::Unused | BorrowState::Shared => self.try_peek().ok(),
+            BorrowState::Mutable | BorrowState::Untracked => None,
+        };
+        match contents {
+            Some(t) => t.hash(state),
+            None => (self.as_ptr() as *const ()).hash(state),
+        }
+    }
+}
+
+/// Two handles to the same instance are equal without borrowing the contents, with either backend, so comparing a cell
+/// with itself never panics, even while it is mutably borrowed. Use `SCell::content_eq` for contents that aren't equal
+/// to themselves, such as a NaN float, or `PtrKey` to compare by identity alone.
+impl<T: ?Sized> PartialEq for SCell<T>
+    where T: PartialEq
+{
+    #[inline]
+    #[track_caller]
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || *self.borrow() == *other.borrow()
+    }
+}
+
+impl<T: ?Sized> Eq for SCell<T> where T: Eq {}
+
+/// Like `PartialEq`, two handles to the same instance compare as equal without borrowing the contents.
+impl<T: ?Sized> PartialOrd for SCell<T>
+    where T: PartialOrd
+{
+    #[inline]
+    #[track_caller]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.ptr_eq(other) {
+            Some(Ordering::Equal)
+        } else {
+            (*self.borrow()).partial_cmp(&*other.borrow())
+        }
+    }
+
+    #[inline]
+    #[track_caller]
+    fn lt(&self, other: &Self) -> bool {
+        !self.ptr_eq(other) && *self.borrow() < *other.borrow()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn le(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || *self.borrow() <= *other.borrow()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn gt(&self, other: &Self) -> bool {
+        !self.ptr_eq(other) && *self.borrow() > *other.borrow()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn ge(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || *self.borrow() >= *other.borrow()
+    }
+}
+
+/// This compares the contents, so a key in an ordered collection must not be mutated while it is in there, and
+/// comparing one that is mutably borrowed panics. Use `SCell::try_cmp` where that can happen.
+impl<T: ?Sized> Ord for SCell<T>
+    where T: Ord
+{
+    #[inline]
+    #[track_caller]
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self.ptr_eq(other) {
+            Ordering::Equal
+        } else {
+            self.borrow().cmp(&*other.borrow())
+        }
     }
 }
 
+/// With the `std` feature, an instance that is displayed again from inside its own `Display` impl, such as through a
+/// cycle of neighbors, is written as `<cycle>` instead of recursing forever.
 impl<T: ?Sized> Display for SCell<T>
     where T: Display
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        fmt_acyclic(self.as_ptr() as *const (), f, |f| self.borrow().fmt(f))
+    }
+}
+
+impl<T: ?Sized> LowerHex for SCell<T>
+    where T: LowerHex
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        self.borrow().fmt(f)
+    }
+}
+
+impl<T: ?Sized> UpperHex for SCell<T>
+    where T: UpperHex
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        self.borrow().fmt(f)
+    }
+}
+
+impl<T: ?Sized> Binary for SCell<T>
+    where T: Binary
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        self.borrow().fmt(f)
+    }
+}
+
+impl<T: ?Sized> Octal for SCell<T>
+    where T: Octal
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        self.borrow().fmt(f)
+    }
+}
+
+impl<T: ?Sized> LowerExp for SCell<T>
+    where T: LowerExp
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        self.borrow().fmt(f)
+    }
+}
+
+impl<T: ?Sized> UpperExp for SCell<T>
+    where T: UpperExp
 {
     #[inline]
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
@@ -52,6 +358,7 @@ impl<T: ?Sized> Display for SCell<T>
     }
 }
 
+/// This formats the contents, so it recurses forever on a cyclic graph. Use `SCell::debug_shallow` for those.
 impl<T: ?Sized> Debug for SCell<T>
     where T: Debug
 {
@@ -61,9 +368,1562 @@ impl<T: ?Sized> Debug for SCell<T>
     }
 }
 
-impl<T> From<T> for SCell<T> {
+/// The contents are only borrowed while formatting them, so `source` can't return a reference into them and always
+/// returns `None`. Borrow the contents to walk their chain of sources instead.
+impl<T: ?Sized> error::Error for SCell<T>
+    where T: error::Error
+{}
+
+impl<'a, T: 'a + ?Sized> Ref<'a, T> {
+    /// Gets a pinned reference to the borrowed data
+    ///
+    /// # Safety
+    ///
+    /// `SCell` can't stop its contents from being moved, so the caller must uphold the pinning invariant themselves:
+    /// from this point on, the contents must not be moved through any handle, such as with `replace`, `swap`,
+    /// `try_unwrap` or `mem::swap` on a `RefMut`, until they are dropped.
     #[inline]
-    fn from(t: T) -> Self {
-        SCell::new(t)
+    pub unsafe fn as_pin_ref(orig: &Self) -> Pin<&T> {
+        Pin::new_unchecked(&**orig)
+    }
+
+    /// Clones the borrowed data
+    ///
+    /// This is an associated function so that it doesn't shadow a method on the contents.
+    #[inline]
+    pub fn cloned(orig: &Self) -> T
+        where T: Clone
+    {
+        (**orig).clone()
+    }
+
+    /// Makes an owned copy of the borrowed data, such as a `String` from a `Ref<str>`
+    ///
+    /// This is an associated function so that it doesn't shadow a method on the contents.
+    #[inline]
+    pub fn to_owned(orig: &Self) -> T::Owned
+        where T: ToOwned
+    {
+        (**orig).to_owned()
+    }
+}
+
+impl<'a, T: 'a + ?Sized> RefMut<'a, T> {
+    /// Gets a pinned mutable reference to the borrowed data
+    ///
+    /// # Safety
+    ///
+    /// `SCell` can't stop its contents from being moved, so the caller must uphold the pinning invariant themselves:
+    /// from this point on, the contents must not be moved through any handle, such as with `replace`, `swap`,
+    /// `try_unwrap` or `mem::swap` on a `RefMut`, until they are dropped.
+    #[inline]
+    pub unsafe fn as_pin_mut(orig: &mut Self) -> Pin<&mut T> {
+        Pin::new_unchecked(&mut **orig)
+    }
+
+    /// Pins the guard, so that `Pin::as_mut` gives out pinned mutable references to the borrowed data for as long as
+    /// it lives
+    ///
+    /// # Safety
+    ///
+    /// This has the same contract as `as_pin_mut`: from this point on, the contents must not be moved through any
+    /// handle until they are dropped, even after the guard is.
+    #[inline]
+    pub unsafe fn into_pin(orig: Self) -> Pin<Self> {
+        Pin::new_unchecked(orig)
+    }
+
+    /// Clones the borrowed data, such as to keep a copy of the result of a mutation
+    ///
+    /// This is an associated function so that it doesn't shadow a method on the contents.
+    #[inline]
+    pub fn cloned(orig: &Self) -> T
+        where T: Clone
+    {
+        (**orig).clone()
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Debug for DebugShallow<'a, T> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_struct("SCell")
+            .field("ptr", &format_args!("{:p}", *self.0))
+            .field("strong_count", &self.0.strong_count())
+            .finish()
+    }
+}
+
+impl<'a, T: 'a + ?Sized> FreezeGuard<'a, T> {
+    /// Unfreezes the instance, which is the same as dropping the guard
+    #[inline]
+    pub fn unfreeze(_guard: Self) {}
+}
+
+impl<'a, T: 'a + ?Sized> Deref for FreezeGuard<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Debug for FreezeGuard<'a, T>
+    where T: Debug
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Deref for EnterGuard<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<'a, T: 'a + ?Sized> DerefMut for EnterGuard<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Debug for EnterGuard<'a, T>
+    where T: Debug
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a, T: 'a + ?Sized, Idx> Index<Idx> for Ref<'a, T>
+    where T: Index<Idx>
+{
+    type Output = T::Output;
+
+    #[inline]
+    fn index(&self, index: Idx) -> &T::Output {
+        &(**self)[index]
+    }
+}
+
+impl<'a, T: 'a + ?Sized, Idx> Index<Idx> for RefMut<'a, T>
+    where T: Index<Idx>
+{
+    type Output = T::Output;
+
+    #[inline]
+    fn index(&self, index: Idx) -> &T::Output {
+        &(**self)[index]
+    }
+}
+
+impl<'a, T: 'a + ?Sized, Idx> IndexMut<Idx> for RefMut<'a, T>
+    where T: IndexMut<Idx>
+{
+    #[inline]
+    fn index_mut(&mut self, index: Idx) -> &mut T::Output {
+        &mut (**self)[index]
+    }
+}
+
+impl<'a, 'b, T: 'a + ?Sized> IntoIterator for &'b Ref<'a, T>
+    where &'b T: IntoIterator
+{
+    type Item = <&'b T as IntoIterator>::Item;
+    type IntoIter = <&'b T as IntoIterator>::IntoIter;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        (&**self).into_iter()
+    }
+}
+
+impl<'a, 'b, T: 'a + ?Sized> IntoIterator for &'b RefMut<'a, T>
+    where &'b T: IntoIterator
+{
+    type Item = <&'b T as IntoIterator>::Item;
+    type IntoIter = <&'b T as IntoIterator>::IntoIter;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        (&**self).into_iter()
+    }
+}
+
+impl<'a, 'b, T: 'a + ?Sized> IntoIterator for &'b mut RefMut<'a, T>
+    where &'b mut T: IntoIterator
+{
+    type Item = <&'b mut T as IntoIterator>::Item;
+    type IntoIter = <&'b mut T as IntoIterator>::IntoIter;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        (&mut **self).into_iter()
+    }
+}
+
+impl<T: ?Sized> Clone for PtrKey<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        PtrKey(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> PartialEq for PtrKey<T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.0.ptr_eq(&other.0)
+    }
+}
+
+impl<T: ?Sized> Eq for PtrKey<T> {}
+
+impl<T: ?Sized> Hash for PtrKey<T> {
+    #[inline]
+    fn hash<H>(&self, state: &mut H)
+        where H: Hasher
+    {
+        (self.0.as_ptr() as *const ()).hash(state);
+    }
+}
+
+impl<T: ?Sized> Debug for PtrKey<T> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_tuple("PtrKey").field(&format_args!("{:p}", self.0)).finish()
+    }
+}
+
+impl<T: ?Sized> Pointer for PtrKey<T> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        Pointer::fmt(&self.0, f)
+    }
+}
+
+impl<T: ?Sized> From<SCell<T>> for PtrKey<T> {
+    #[inline]
+    fn from(cell: SCell<T>) -> Self {
+        PtrKey(cell)
+    }
+}
+
+impl<T: ?Sized> PtrOrd<T> {
+    #[inline]
+    fn addr(&self) -> *const () {
+        self.0.as_ptr() as *const ()
+    }
+}
+
+impl<T: ?Sized> Clone for PtrOrd<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        PtrOrd(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> PartialEq for PtrOrd<T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.0.ptr_eq(&other.0)
+    }
+}
+
+impl<T: ?Sized> Eq for PtrOrd<T> {}
+
+impl<T: ?Sized> PartialOrd for PtrOrd<T> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: ?Sized> Ord for PtrOrd<T> {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.addr().cmp(&other.addr())
+    }
+}
+
+impl<T: ?Sized> Hash for PtrOrd<T> {
+    #[inline]
+    fn hash<H>(&self, state: &mut H)
+        where H: Hasher
+    {
+        self.addr().hash(state);
+    }
+}
+
+impl<T: ?Sized> Debug for PtrOrd<T> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.debug_tuple("PtrOrd").field(&format_args!("{:p}", self.0)).finish()
+    }
+}
+
+impl<T: ?Sized> Pointer for PtrOrd<T> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        Pointer::fmt(&self.0, f)
+    }
+}
+
+impl<T: ?Sized> From<SCell<T>> for PtrOrd<T> {
+    #[inline]
+    fn from(cell: SCell<T>) -> Self {
+        PtrOrd(cell)
+    }
+}
+
+impl<T: ?Sized, O> Add for &SCell<T>
+    where for<'x> &'x T: Add<&'x T, Output = O>
+{
+    type Output = O;
+
+    #[inline]
+    #[track_caller]
+    fn add(self, other: Self) -> O {
+        &*self.borrow() + &*other.borrow()
+    }
+}
+
+impl<T: ?Sized, O> Sub for &SCell<T>
+    where for<'x> &'x T: Sub<&'x T, Output = O>
+{
+    type Output = O;
+
+    #[inline]
+    #[track_caller]
+    fn sub(self, other: Self) -> O {
+        &*self.borrow() - &*other.borrow()
+    }
+}
+
+impl<T: ?Sized, O> Mul for &SCell<T>
+    where for<'x> &'x T: Mul<&'x T, Output = O>
+{
+    type Output = O;
+
+    #[inline]
+    #[track_caller]
+    fn mul(self, other: Self) -> O {
+        &*self.borrow() * &*other.borrow()
+    }
+}
+
+impl<T: ?Sized, O> Div for &SCell<T>
+    where for<'x> &'x T: Div<&'x T, Output = O>
+{
+    type Output = O;
+
+    #[inline]
+    #[track_caller]
+    fn div(self, other: Self) -> O {
+        &*self.borrow() / &*other.borrow()
+    }
+}
+
+impl<T: ?Sized, O> Neg for &SCell<T>
+    where for<'x> &'x T: Neg<Output = O>
+{
+    type Output = O;
+
+    #[inline]
+    #[track_caller]
+    fn neg(self) -> O {
+        -&*self.borrow()
+    }
+}
+
+impl<T: ?Sized> PartialEq<T> for SCell<T>
+    where T: PartialEq
+{
+    #[inline]
+    #[track_caller]
+    fn eq(&self, other: &T) -> bool {
+        *self.borrow() == *other
+    }
+}
+
+impl<'a, T: 'a + ?Sized> PartialEq<T> for Ref<'a, T>
+    where T: PartialEq
+{
+    #[inline]
+    fn eq(&self, other: &T) -> bool {
+        **self == *other
+    }
+}
+
+impl<'a, T: 'a + ?Sized> PartialEq<T> for RefMut<'a, T>
+    where T: PartialEq
+{
+    #[inline]
+    fn eq(&self, other: &T) -> bool {
+        **self == *other
+    }
+}
+
+/// Comparing a guard with an `SCell` reads the contents of the `SCell` through the guard if it borrows that same
+/// instance, so comparing a `RefMut` with the cell it came from doesn't panic.
+impl<'a, T: 'a + ?Sized> PartialEq<SCell<T>> for Ref<'a, T>
+    where T: PartialEq
+{
+    #[inline]
+    fn eq(&self, other: &SCell<T>) -> bool {
+        with_contents(&**self, other, |lhs, rhs| lhs == rhs)
+    }
+}
+
+impl<'a, T: 'a + ?Sized> PartialEq<Ref<'a, T>> for SCell<T>
+    where T: PartialEq
+{
+    #[inline]
+    fn eq(&self, other: &Ref<'a, T>) -> bool {
+        with_contents(&**other, self, |rhs, lhs| lhs == rhs)
+    }
+}
+
+impl<'a, T: 'a + ?Sized> PartialOrd<SCell<T>> for Ref<'a, T>
+    where T: PartialOrd
+{
+    #[inline]
+    fn partial_cmp(&self, other: &SCell<T>) -> Option<Ordering> {
+        with_contents(&**self, other, |lhs, rhs| lhs.partial_cmp(rhs))
+    }
+}
+
+impl<'a, T: 'a + ?Sized> PartialOrd<Ref<'a, T>> for SCell<T>
+    where T: PartialOrd
+{
+    #[inline]
+    fn partial_cmp(&self, other: &Ref<'a, T>) -> Option<Ordering> {
+        with_contents(&**other, self, |rhs, lhs| lhs.partial_cmp(rhs))
+    }
+}
+
+/// Comparing a guard with an `SCell` reads the contents of the `SCell` through the guard if it borrows that same
+/// instance, so comparing a `RefMut` with the cell it came from doesn't panic.
+impl<'a, T: 'a + ?Sized> PartialEq<SCell<T>> for RefMut<'a, T>
+    where T: PartialEq
+{
+    #[inline]
+    fn eq(&self, other: &SCell<T>) -> bool {
+        with_contents(&**self, other, |lhs, rhs| lhs == rhs)
+    }
+}
+
+impl<'a, T: 'a + ?Sized> PartialEq<RefMut<'a, T>> for SCell<T>
+    where T: PartialEq
+{
+    #[inline]
+    fn eq(&self, other: &RefMut<'a, T>) -> bool {
+        with_contents(&**other, self, |rhs, lhs| lhs == rhs)
+    }
+}
+
+impl<'a, T: 'a + ?Sized> PartialOrd<SCell<T>> for RefMut<'a, T>
+    where T: PartialOrd
+{
+    #[inline]
+    fn partial_cmp(&self, other: &SCell<T>) -> Option<Ordering> {
+        with_contents(&**self, other, |lhs, rhs| lhs.partial_cmp(rhs))
+    }
+}
+
+impl<'a, T: 'a + ?Sized> PartialOrd<RefMut<'a, T>> for SCell<T>
+    where T: PartialOrd
+{
+    #[inline]
+    fn partial_cmp(&self, other: &RefMut<'a, T>) -> Option<Ordering> {
+        with_contents(&**other, self, |rhs, lhs| lhs.partial_cmp(rhs))
+    }
+}
+
+impl<T: ?Sized> Debug for SWeak<T> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.write_str("(SWeak)")
+    }
+}
+
+impl<T> SWeak<T> {
+    /// Upgrades this handle, or if the contents have been dropped, constructs a new `SCell` from the value returned by
+    /// `f` and points this handle at it
+    ///
+    /// This handle only stays weak, so the new instance is dropped again once the returned `SCell` and its clones are
+    /// gone. Other `SWeak` handles to the old instance are left pointing at it and can't be upgraded.
+    #[inline]
+    pub fn upgrade_or_insert_with<F>(&mut self, f: F) -> SCell<T>
+        where F: FnOnce() -> T
+    {
+        match self.upgrade() {
+            Some(cell) => cell,
+            None => {
+                let cell = SCell::new(f());
+                *self = cell.downgrade();
+                cell
+            }
+        }
+    }
+}
+
+impl<T: ?Sized> SCell<T> {
+    /// Runs `f` with a borrow of the contents that is released before this returns
+    #[inline]
+    #[track_caller]
+    pub fn with<R, F>(&self, f: F) -> R
+        where F: FnOnce(&T) -> R
+    {
+        f(&self.borrow())
+    }
+
+    /// Computes an owned value from the contents, releasing the borrow before this returns
+    ///
+    /// This is the same as `with`, but reads better when extracting something from the contents. Unlike `Ref::map`,
+    /// the result doesn't keep the contents borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn map<U, F>(&self, f: F) -> U
+        where F: FnOnce(&T) -> U
+    {
+        self.with(f)
+    }
+
+    /// Runs `f` with a borrow of the contents and returns this handle, for looking at a node in the middle of a chain
+    ///
+    /// The borrow is released before this returns, so the next call in the chain can borrow the contents mutably.
+    #[inline]
+    #[track_caller]
+    pub fn inspect<F>(&self, f: F) -> &Self
+        where F: FnOnce(&T)
+    {
+        f(&self.borrow());
+        self
+    }
+
+    /// Runs `f` with a mutable borrow of the contents that is released before this returns
+    #[inline]
+    #[track_caller]
+    pub fn with_mut<R, F>(&self, f: F) -> R
+        where F: FnOnce(&mut T) -> R
+    {
+        f(&mut self.borrow_mut())
+    }
+
+    /// Runs `f` with a mutable borrow of the contents, or returns an error without running it if they are borrowed
+    #[inline]
+    #[track_caller]
+    pub fn try_with_mut<R, F>(&self, f: F) -> Result<R, BorrowMutError>
+        where F: FnOnce(&mut T) -> R
+    {
+        self.try_borrow_mut().map(|mut borrow| f(&mut borrow))
+    }
+
+    /// Checks if the contents are currently borrowed, either mutably or not
+    ///
+    /// This only reads the borrow flag and never makes a reference to the contents. The unchecked backend only has a
+    /// flag when `debug_assertions` are enabled, so this is always `false` in release.
+    #[inline]
+    pub fn is_borrowed(&self) -> bool {
+        match self.borrow_state() {
+            BorrowState::Shared | BorrowState::Mutable => true,
+            BorrowState::Unused | BorrowState::Untracked => false,
+        }
+    }
+
+    /// Checks if the contents are currently mutably borrowed
+    ///
+    /// This only reads the borrow flag and never makes a reference to the contents. The unchecked backend only has a
+    /// flag when `debug_assertions` are enabled, so this is always `false` in release.
+    #[inline]
+    pub fn is_borrowed_mut(&self) -> bool {
+        self.borrow_state() == BorrowState::Mutable
+    }
+
+    /// Freezes the instance so that mutably borrowing it fails until the returned guard is dropped
+    ///
+    /// This is for read-only phases, so that an accidental write is caught instead of relying on discipline. Panics if
+    /// the contents are mutably borrowed. The unchecked backend only tracks borrows when `debug_assertions` are
+    /// enabled, so in release this doesn't stop anything.
+    #[inline]
+    #[track_caller]
+    pub fn freeze(&self) -> FreezeGuard<'_, T> {
+        FreezeGuard(self.borrow())
+    }
+
+    /// Enters the instance for a traversal, or returns `None` if it is already borrowed, such as by an `EnterGuard`
+    /// further up the stack
+    ///
+    /// The unchecked backend only tracks borrows when `debug_assertions` are enabled, so in release this always
+    /// succeeds. Entering an instance that is already entered then makes a second `&mut` to the same contents, which is
+    /// undefined behavior, so with `unchecked` this can't be used to detect cycles in release.
+    #[inline]
+    #[track_caller]
+    pub fn try_enter(&self) -> Option<EnterGuard<'_, T>> {
+        self.try_borrow_mut().ok().map(EnterGuard)
+    }
+
+    /// Compares the contents like `==`, but returns `None` instead of panicking if either can't be borrowed
+    #[inline]
+    #[track_caller]
+    pub fn try_eq(&self, other: &Self) -> Option<bool>
+        where T: PartialEq
+    {
+        let lhs = self.try_borrow().ok()?;
+        let rhs = other.try_borrow().ok()?;
+        Some(*lhs == *rhs)
+    }
+
+    /// Checks if one of the two handles could replace the other, because they refer to the same instance or their
+    /// contents are equal
+    ///
+    /// This is for deduplicating nodes, so it never panics: contents that can't be borrowed count as not mergeable.
+    #[inline]
+    pub fn mergeable(&self, other: &Self) -> bool
+        where T: PartialEq
+    {
+        self.ptr_eq(other) || self.try_eq(other).unwrap_or(false)
+    }
+
+    /// Compares the contents like `Ord::cmp`, but returns `None` instead of panicking if either can't be borrowed
+    ///
+    /// The contents of a key in an ordered collection like `BTreeSet` must not change while it's in there, and a key
+    /// that is mutably borrowed makes the `Ord` impl panic. This is the safe way to compare nodes that might be in the
+    /// middle of being mutated.
+    #[inline]
+    #[track_caller]
+    pub fn try_cmp(&self, other: &Self) -> Option<Ordering>
+        where T: Ord
+    {
+        let lhs = self.try_borrow().ok()?;
+        let rhs = other.try_borrow().ok()?;
+        Some((*lhs).cmp(&*rhs))
+    }
+
+    /// Gets a `Debug` view of this instance that shows its address and strong count instead of its contents
+    ///
+    /// The `Debug` impl for `SCell` formats the contents, which overflows the stack if they lead back to this instance.
+    /// This never borrows the contents, so it is safe to use on cyclic graphs and on cells that are mutably borrowed.
+    #[inline]
+    pub fn debug_shallow(&self) -> impl Debug + '_ {
+        DebugShallow(self)
+    }
+
+    /// Compares the contents with `==` without treating the same instance as equal to itself
+    ///
+    /// `PartialEq` for `SCell` returns `true` for two handles to the same instance without looking at the contents,
+    /// which is wrong for contents that aren't equal to themselves, such as a NaN float.
+    #[inline]
+    #[track_caller]
+    pub fn content_eq(&self, other: &Self) -> bool
+        where T: PartialEq
+    {
+        *self.borrow() == *other.borrow()
+    }
+
+    /// Compares the contents with `PartialOrd::partial_cmp` without treating the same instance as equal to itself
+    ///
+    /// `PartialOrd` for `SCell` returns `Some(Equal)` for two handles to the same instance, but a NaN float isn't
+    /// comparable to itself, so this returns `None` for it where `partial_cmp` wouldn't.
+    #[inline]
+    #[track_caller]
+    pub fn content_cmp(&self, other: &Self) -> Option<Ordering>
+        where T: PartialOrd
+    {
+        (*self.borrow()).partial_cmp(&*other.borrow())
+    }
+
+    /// Mutably borrows the contents as a pinned guard, for contents that are `!Unpin`, such as a future
+    ///
+    /// Panics like `borrow_mut` if the contents are borrowed.
+    ///
+    /// # Safety
+    ///
+    /// The caller must uphold the pinning contract of `RefMut::into_pin` for the contents of this instance.
+    #[inline]
+    #[track_caller]
+    pub unsafe fn borrow_mut_pin(&self) -> Pin<RefMut<'_, T>> {
+        RefMut::into_pin(self.borrow_mut())
+    }
+
+    /// An alias of `borrow` for call sites that read better with `AsRef`-style naming
+    #[inline]
+    #[track_caller]
+    pub fn as_ref(&self) -> Ref<'_, T> {
+        self.borrow()
+    }
+
+    /// An alias of `borrow_mut` for call sites that read better with `AsMut`-style naming
+    #[inline]
+    #[track_caller]
+    pub fn as_mut(&self) -> RefMut<'_, T> {
+        self.borrow_mut()
+    }
+}
+
+impl<T> SCell<T> {
+    /// Replaces the contents with `t` if `pred` returns true for them, returning the old contents, or otherwise returns
+    /// `t` back
+    ///
+    /// The contents are mutably borrowed once for both the test and the replacement, so they can't change in between,
+    /// but `pred` must not borrow this instance itself. Panics if the contents are currently borrowed.
+    #[inline]
+    #[track_caller]
+    pub fn replace_if<F>(&self, pred: F, t: T) -> Result<T, T>
+        where F: FnOnce(&T) -> bool
+    {
+        let mut contents = self.borrow_mut();
+        if pred(&contents) {
+            Ok(mem::replace(&mut *contents, t))
+        } else {
+            Err(t)
+        }
+    }
+
+    /// Converts the contents with `TryFrom` and puts the result in a new `SCell` if this is the only handle to them
+    #[inline]
+    pub fn try_convert<U>(self) -> Result<SCell<U>, ConvertError<T, U::Error>>
+        where U: TryFrom<T>
+    {
+        let t = SCell::try_unwrap(self).map_err(ConvertError::Shared)?;
+        U::try_from(t).map(SCell::new).map_err(ConvertError::Failed)
+    }
+
+    /// Points this handle at a new instance holding `t`, leaving the other handles to the old instance untouched
+    ///
+    /// This is not the same as `replace`, which changes the contents that every handle to the instance sees. After
+    /// this, this handle no longer shares anything with its former clones. If it was the only handle, the old contents
+    /// are dropped.
+    #[inline]
+    pub fn rebind(&mut self, t: T) {
+        *self = SCell::new(t);
+    }
+
+    /// Swaps the contents of two distinct instances, or returns an error instead of swapping
+    ///
+    /// Unlike `swap`, which does nothing when both handles refer to the same instance, this fails in that case. It also
+    /// fails if either is borrowed, although the unchecked backend can only detect that when `debug_assertions` are
+    /// enabled.
+    #[inline]
+    #[track_caller]
+    pub fn swap_checked(&self, other: &Self) -> Result<(), SwapError> {
+        if self.ptr_eq(other) {
+            return Err(SwapError::Aliased);
+        }
+        let mut lhs = self.try_borrow_mut().map_err(|_| SwapError::Borrowed)?;
+        let mut rhs = other.try_borrow_mut().map_err(|_| SwapError::Borrowed)?;
+        mem::swap(&mut *lhs, &mut *rhs);
+        Ok(())
+    }
+
+    /// Constructs a new `SCell`, exactly like `new`
+    ///
+    /// Neither of these can be `const` because they allocate. Use `LazySCell` to defer construction where a `const`
+    /// initializer is needed, such as in `thread_local!`.
+    #[inline]
+    pub fn from_value(t: T) -> Self {
+        SCell::new(t)
+    }
+
+    /// Constructs a new `SCell` from the value returned by `f`
+    #[inline]
+    pub fn default_with<F>(f: F) -> Self
+        where F: FnOnce() -> T
+    {
+        SCell::new(f())
+    }
+
+    /// Constructs a new `SCell` from the value in `r`, or returns its error, for building nodes from fallible factories
+    /// with `?`
+    #[inline]
+    pub fn try_new<E>(r: Result<T, E>) -> Result<Self, E> {
+        r.map(SCell::new)
+    }
+
+    /// Returns the contents if this is the only `SCell` handle to them
+    ///
+    /// When crates in the same build enable different backends, their `SCell` types are distinct, so values have to
+    /// be moved across the boundary by unwrapping them with this and rebuilding them with `SCell::new` on the other
+    /// side. Shared instances can't be moved this way since the other handles would be left behind.
+    #[inline]
+    pub fn into_value(self) -> Option<T> {
+        SCell::try_unwrap(self).ok()
+    }
+}
+
+impl<T> SCell<T>
+    where T: Default
+{
+    /// Takes the contents, leaving `T::default()` in their place
+    #[inline]
+    #[track_caller]
+    pub fn take(&self) -> T {
+        self.replace(T::default())
+    }
+}
+
+impl<T> SCell<T>
+    where T: Clone
+{
+    /// Clones the contents into a new, independent instance
+    ///
+    /// This differs from `clone`, which creates another handle to the same instance, so changes made through the
+    /// returned `SCell` are not visible through this one.
+    #[inline]
+    #[track_caller]
+    pub fn clone_inner(&self) -> SCell<T> {
+        SCell::new(self.borrow().clone())
+    }
+
+    /// Clones the contents into a new, independent instance, or returns an error if they are mutably borrowed
+    ///
+    /// The unchecked backend only tracks borrows when `debug_assertions` are enabled, so this always succeeds in
+    /// release.
+    #[inline]
+    #[track_caller]
+    pub fn try_clone_inner(&self) -> Result<SCell<T>, BorrowError> {
+        self.try_borrow().map(|t| SCell::new(t.clone()))
+    }
+
+    /// Mutably borrows the contents, first cloning them into a new instance if there are other handles to them
+    ///
+    /// This gives copy-on-write semantics like `Rc::make_mut`, so other `SCell` handles keep the old instance and
+    /// `SWeak` handles are disassociated from this one.
+    #[inline]
+    #[track_caller]
+    pub fn make_mut(&mut self) -> RefMut<'_, T> {
+        if self.get_mut().is_none() {
+            let t = self.borrow().clone();
+            *self = SCell::new(t);
+        }
+        self.borrow_mut()
+    }
+
+    /// Constructs a new `SCell` from the value in an `Rc`, moving it out if that was the only handle and cloning it
+    /// otherwise
+    ///
+    /// `SCell` keeps its bookkeeping in the same allocation as the contents, so it can never share the allocation of
+    /// an `Rc<T>`. The other handles to the `Rc` keep their own copy, and changes made through the returned `SCell`
+    /// are not visible through them.
+    #[inline]
+    pub fn from_rc(rc: Rc<T>) -> Self {
+        SCell::new(Rc::unwrap_or_clone(rc))
+    }
+}
+
+impl<T> From<T> for SCell<T> {
+    #[inline]
+    fn from(t: T) -> Self {
+        SCell::new(t)
+    }
+}
+
+/// This moves the value out of the `Box` into a new allocation, since the `Box` has no room for the bookkeeping.
+impl<T> From<Box<T>> for SCell<T> {
+    #[inline]
+    fn from(b: Box<T>) -> Self {
+        SCell::new(*b)
+    }
+}
+
+/// Borrows two distinct instances at once
+///
+/// Returns `None` if `a` and `b` are the same instance, even though that wouldn't conflict, so that code written
+/// against this can rely on the two guards never aliasing. Also returns `None` if either is mutably borrowed.
+#[inline]
+#[track_caller]
+pub fn borrow_pair<'a, T: ?Sized>(a: &'a SCell<T>, b: &'a SCell<T>) -> Option<(Ref<'a, T>, Ref<'a, T>)> {
+    if a.ptr_eq(b) {
+        return None;
+    }
+    Some((a.try_borrow().ok()?, b.try_borrow().ok()?))
+}
+
+/// Mutably borrows two distinct instances at once
+///
+/// Returns `None` if `a` and `b` are the same instance, which guarantees the two guards never alias, even with the
+/// `unchecked` feature where borrowing the same instance mutably twice is undefined behavior. Also returns `None` if
+/// either is already borrowed.
+#[inline]
+#[track_caller]
+pub fn borrow_mut_pair<'a, T: ?Sized>(a: &'a SCell<T>, b: &'a SCell<T>) -> Option<(RefMut<'a, T>, RefMut<'a, T>)> {
+    if a.ptr_eq(b) {
+        return None;
+    }
+    Some((a.try_borrow_mut().ok()?, b.try_borrow_mut().ok()?))
+}
+
+/// Moves each value into its own new `SCell`
+#[inline]
+pub fn wrap_all<T>(values: Vec<T>) -> Vec<SCell<T>> {
+    values.into_iter().map(SCell::new).collect()
+}
+
+/// Returns the contents of every cell if each is the only `SCell` handle to its contents, otherwise returns all of the
+/// handles untouched
+///
+/// The handles are all checked before any is unwrapped, so a single shared cell leaves every other one intact. This
+/// includes a cell that appears twice in `cells`.
+pub fn unwrap_all<T>(cells: Vec<SCell<T>>) -> Result<Vec<T>, Vec<SCell<T>>> {
+    if cells.iter().any(|cell| cell.strong_count() != 1) {
+        return Err(cells);
+    }
+    Ok(cells.into_iter().map(SCell::into_inner).collect())
+}
+
+impl<T, E> Display for ConvertError<T, E>
+    where E: Display
+{
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match *self {
+            ConvertError::Shared(_) => f.write_str("cannot convert the contents of a shared SCell"),
+            ConvertError::Failed(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl<T, E> error::Error for ConvertError<T, E>
+    where T: Debug, E: error::Error + 'static
+{
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ConvertError::Shared(_) => None,
+            ConvertError::Failed(ref e) => Some(e),
+        }
+    }
+}
+
+impl Display for SwapError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match *self {
+            SwapError::Aliased => f.write_str("cannot swap an SCell with itself"),
+            SwapError::Borrowed => f.write_str("already borrowed"),
+        }
+    }
+}
+
+impl error::Error for SwapError {}
+
+#[cfg(feature = "std")]
+thread_local!(static FORMATTING: std::cell::RefCell<Vec<*const ()>> = const { std::cell::RefCell::new(Vec::new()) });
+
+/// Removes the innermost instance from `FORMATTING` once it is done, even if formatting it panics
+#[cfg(feature = "std")]
+struct FormattingGuard;
+
+#[cfg(feature = "std")]
+impl Drop for FormattingGuard {
+    #[inline]
+    fn drop(&mut self) {
+        FORMATTING.with(|formatting| formatting.borrow_mut().pop());
+    }
+}
+
+/// Runs `fmt` unless the instance at `ptr` is already being formatted further up the stack, in which case it writes
+/// `<cycle>` instead
+#[cfg(feature = "std")]
+fn fmt_acyclic<F>(ptr: *const (), f: &mut Formatter, fmt: F) -> Result<(), Error>
+    where F: FnOnce(&mut Formatter) -> Result<(), Error>
+{
+    if FORMATTING.with(|formatting| formatting.borrow().contains(&ptr)) {
+        return f.write_str("<cycle>");
+    }
+    FORMATTING.with(|formatting| formatting.borrow_mut().push(ptr));
+    let _guard = FormattingGuard;
+    fmt(f)
+}
+
+/// Without `std` there is no thread-local storage to track instances in, so this always runs `fmt`
+#[cfg(not(feature = "std"))]
+#[inline]
+fn fmt_acyclic<F>(_: *const (), f: &mut Formatter, fmt: F) -> Result<(), Error>
+    where F: FnOnce(&mut Formatter) -> Result<(), Error>
+{
+    fmt(f)
+}
+
+/// Runs `f` on the contents borrowed by `guard` and the contents of `cell`, reusing `guard` instead of borrowing `cell`
+/// again if they are the same instance
+#[inline]
+fn with_contents<T: ?Sized, R, F>(guard: &T, cell: &SCell<T>, f: F) -> R
+    where F: FnOnce(&T, &T) -> R
+{
+    if guard as *const T as *const () == cell.as_ptr() as *const () {
+        f(guard, guard)
+    } else {
+        f(guard, &cell.borrow())
+    }
+}
+
+/// Moves the elements of `v` into a new allocation laid out like `empty` with the elements as its trailing slice, and
+/// returns the start of the allocation
+///
+/// `tail` must point to the empty trailing slice of `empty`. Any type ending in a slice is laid out the same way for
+/// every length, so copying the bytes in front of `tail` and appending the elements gives a valid value of it with the
+/// length of `v`. The allocation has the layout that `Box` computes for that value, so it can be owned by one.
+unsafe fn alloc_with_tail<H: ?Sized, T>(empty: &H, tail: *const T, mut v: Vec<T>) -> *mut T {
+    let offset = tail as usize - empty as *const H as *const u8 as usize;
+    let size = mem::size_of::<T>().checked_mul(v.len()).and_then(|size| size.checked_add(offset))
+        .expect("capacity overflow");
+    let layout = Layout::from_size_align(size, mem::align_of_val(empty)).expect("capacity overflow").pad_to_align();
+    // The header is empty in release with the unchecked backend, so an empty slice or one of zero-sized elements
+    // needs no memory at all, and allocating zero bytes is undefined behavior. `Box` never frees a zero-sized value.
+    let start = if layout.size() == 0 {
+        ptr::without_provenance_mut(layout.align())
+    } else {
+        alloc::alloc::alloc(layout)
+    };
+    if start.is_null() {
+        alloc::alloc::handle_alloc_error(layout);
+    }
+    ptr::copy_nonoverlapping(empty as *const H as *const u8, start, offset);
+    ptr::copy_nonoverlapping(v.as_ptr(), start.add(offset) as *mut T, v.len());
+    // The elements now belong to the new allocation, so only the `Vec`'s buffer is freed.
+    v.set_len(0);
+    start as *mut T
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::rc::Rc;
+    use alloc::string::String;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::convert::TryFrom;
+    use SCell;
+
+    #[test]
+    fn from_vec_moves_elements() {
+        let cell = SCell::from_vec(vec![String::from("a"), String::from("bc")]);
+        assert_eq!(cell.borrow().len(), 2);
+        assert_eq!(cell.borrow()[1], "bc");
+        cell.borrow_mut()[0].push('d');
+        assert_eq!(cell.borrow()[0], "ad");
+    }
+
+    #[test]
+    fn from_vec_drops_elements_once() {
+        let counted = Rc::new(());
+        let cell = SCell::from_vec(vec![counted.clone(), counted.clone()]);
+        assert_eq!(Rc::strong_count(&counted), 3);
+        drop(cell);
+        assert_eq!(Rc::strong_count(&counted), 1);
+    }
+
+    #[test]
+    fn from_vec_empty() {
+        let cell: SCell<[u64]> = SCell::from_vec(Vec::new());
+        assert!(cell.borrow().is_empty());
+        assert!(cell.try_borrow_mut().is_ok());
+    }
+
+    #[test]
+    fn from_vec_zero_sized() {
+        let cell = SCell::from_vec(vec![(); 3]);
+        assert_eq!(cell.borrow().len(), 3);
+        let other = cell.clone();
+        drop(cell);
+        assert_eq!(other.borrow().len(), 3);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn ascell_borrow_mut_excludes_other_threads() {
+        let cell = ::ASCell::new(0usize);
+        let threads: Vec<_> = (0..4).map(|_| {
+            let cell = cell.clone();
+            std::thread::spawn(move || for _ in 0..10000 {
+                *cell.borrow_mut() += 1;
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(*cell.borrow(), 40000);
+    }
+
+    // `TryFrom` is only fallible with the unchecked backend, and this has to build with both.
+    #[test]
+    #[allow(clippy::unnecessary_fallible_conversions)]
+    fn try_into_rc_unique() {
+        let rc = SCell::new(3u32).try_into_rc().ok().unwrap();
+        assert_eq!(*rc.borrow(), 3);
+        let cell = SCell::<u32>::try_from(rc).ok().unwrap();
+        assert_eq!(*cell.borrow(), 3);
+    }
+
+    #[cfg(feature = "unchecked")]
+    #[test]
+    fn try_into_rc_shared() {
+        let cell = SCell::new(3);
+        let other = cell.clone();
+        let cell = cell.try_into_rc().err().unwrap();
+        assert!(cell.ptr_eq(&other));
+        let cell = Rc::<core::cell::RefCell<u32>>::try_from(cell).err().unwrap();
+        assert!(cell.ptr_eq(&other));
+    }
+
+    #[cfg(feature = "unchecked")]
+    #[test]
+    fn try_into_rc_unsafe_cell() {
+        use core::cell::UnsafeCell;
+        let cell = SCell::new(3u32);
+        let other = cell.clone();
+        let cell = Rc::<UnsafeCell<u32>>::try_from(cell).err().unwrap();
+        drop(other);
+        let rc = Rc::<UnsafeCell<u32>>::try_from(cell).ok().unwrap();
+        let shared = rc.clone();
+        let rc = SCell::<u32>::try_from(rc).err().unwrap();
+        drop(shared);
+        let cell = SCell::<u32>::try_from(rc).ok().unwrap();
+        assert_eq!(*cell.borrow(), 3);
+    }
+
+    #[cfg(all(feature = "borrow-stats", not(feature = "unchecked")))]
+    #[test]
+    fn borrow_stats_count_guards_but_not_probes() {
+        use core::hash::Hash;
+        use std::collections::hash_map::DefaultHasher;
+        use Ref;
+        let cell = SCell::new(1u32);
+        let other = cell.clone();
+        {
+            let first = cell.borrow();
+            let _second = Ref::clone(&first);
+            assert!(other.is_borrowed());
+            assert!(!other.is_borrowed_mut());
+        }
+        *other.borrow_mut() += 1;
+        cell.hash(&mut DefaultHasher::new());
+        let stats = cell.borrow_stats();
+        assert_eq!((stats.total(), stats.max_concurrent()), (3, 2));
+    }
+
+    #[cfg(all(feature = "track-borrows", not(feature = "unchecked")))]
+    #[test]
+    fn borrow_conflict_display() {
+        use alloc::string::ToString;
+        let cell = SCell::new(0u32);
+        let held = cell.borrow();
+        let message = cell.try_borrow_mut().err().unwrap().to_string();
+        assert!(message.starts_with("borrow attempted at src/lib.rs:"));
+        assert!(message.contains(" conflicts with borrows held at src/lib.rs:"));
+        drop(held);
+    }
+
+    #[cfg(all(feature = "track-borrows", not(feature = "unchecked")))]
+    #[test]
+    fn borrow_panic_hook_runs_before_panic() {
+        use std::cell::Cell;
+        use std::panic::{self, AssertUnwindSafe};
+        use {BorrowFailure, BorrowKind, set_borrow_panic_hook};
+        thread_local!(static SEEN: Cell<Option<(BorrowKind, bool, usize)>> = const { Cell::new(None) });
+        fn hook(failure: &BorrowFailure) {
+            // The hook is shared by every thread, so only the thread running this test records anything.
+            let _ = SEEN.try_with(|seen| seen.set(Some((failure.kind(), std::thread::panicking(), failure.held().len()))));
+        }
+        set_borrow_panic_hook(hook);
+        let cell = SCell::new(0u32);
+        let held = cell.borrow();
+        SEEN.with(|seen| seen.set(None));
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| *cell.borrow_mut() += 1)).is_err());
+        assert_eq!(SEEN.with(Cell::get), Some((BorrowKind::Mutable, false, 1)));
+        drop(held);
+    }
+
+    #[test]
+    fn unwrap_all_unique() {
+        let cells = ::wrap_all(vec![1, 2, 3]);
+        assert_eq!(::unwrap_all(cells).ok().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn unwrap_all_shared_keeps_every_handle() {
+        let cells = ::wrap_all(vec![String::from("a"), String::from("b")]);
+        let shared = cells[1].clone();
+        let cells = ::unwrap_all(cells).err().unwrap();
+        assert_eq!(cells.len(), 2);
+        assert_eq!(*cells[0].borrow(), "a");
+        assert_eq!(cells[0].strong_count(), 1);
+        assert!(cells[1].ptr_eq(&shared));
+        assert_eq!(cells[1].strong_count(), 2);
+    }
+
+    #[test]
+    fn unwrap_all_same_cell_twice() {
+        let cell = SCell::new(1);
+        let cells = ::unwrap_all(vec![cell.clone(), cell]).err().unwrap();
+        assert!(cells[0].ptr_eq(&cells[1]));
+        assert_eq!(*cells[1].borrow(), 1);
+    }
+
+    #[test]
+    fn unwrap_all_ignores_weak_handles() {
+        let cells = ::wrap_all(vec![1, 2]);
+        let weak = cells[0].downgrade();
+        assert_eq!(::unwrap_all(cells).ok().unwrap(), vec![1, 2]);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let cell = SCell::new(String::from("a"));
+        let other = cell.clone();
+        let ptr = SCell::into_raw(cell);
+        assert_eq!(other.strong_count(), 2);
+        let cell = unsafe{SCell::<String>::from_raw(ptr)};
+        assert!(cell.ptr_eq(&other));
+        cell.borrow_mut().push('b');
+        drop(cell);
+        assert_eq!(other.strong_count(), 1);
+        assert_eq!(*other.borrow(), "ab");
+    }
+
+    #[test]
+    fn is_borrowed_reports_guards() {
+        let cell = SCell::new(0u32);
+        assert!(!cell.is_borrowed() && !cell.is_borrowed_mut());
+        let tracked = cfg!(any(not(feature = "unchecked"), debug_assertions));
+        {
+            let _borrow = cell.borrow();
+            assert_eq!((cell.is_borrowed(), cell.is_borrowed_mut()), (tracked, false));
+        }
+        {
+            let _borrow = cell.borrow_mut();
+            assert_eq!((cell.is_borrowed(), cell.is_borrowed_mut()), (tracked, tracked));
+        }
+        assert!(!cell.is_borrowed());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn hash_falls_back_to_address() {
+        use core::hash::{Hash, Hasher};
+        use std::collections::hash_map::DefaultHasher;
+        fn hash<T: Hash>(t: &T) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            t.hash(&mut hasher);
+            hasher.finish()
+        }
+        let (a, b) = (SCell::new(1u32), SCell::new(1u32));
+        let address = hash(&(a.as_ptr() as *const ()));
+        let tracked = cfg!(any(not(feature = "unchecked"), debug_assertions));
+        assert_eq!(hash(&a) == hash(&b), tracked);
+        let _borrow = a.borrow_mut();
+        assert_eq!(hash(&a), address);
+    }
+
+    #[cfg(feature = "graph-utils")]
+    struct Node(Vec<SCell<Node>>);
+
+    #[cfg(feature = "graph-utils")]
+    fn children(node: &Node) -> Vec<SCell<Node>> {
+        node.0.clone()
+    }
+
+    #[cfg(feature = "graph-utils")]
+    #[test]
+    fn check_all_borrowable_mut_finds_borrowed_node() {
+        let leaf = SCell::new(Node(Vec::new()));
+        let roots = [SCell::new(Node(vec![leaf.clone()]))];
+        assert!(SCell::check_all_borrowable_mut(&roots, children).is_ok());
+        {
+            let _borrow = leaf.borrow();
+            let result = SCell::check_all_borrowable_mut(&roots, children);
+            if cfg!(any(not(feature = "unchecked"), debug_assertions)) {
+                assert!(result.err().unwrap().ptr_eq(&leaf));
+            } else {
+                assert!(result.is_ok());
+            }
+        }
+        assert!(SCell::check_all_borrowable_mut(&roots, children).is_ok());
+    }
+
+    #[cfg(feature = "borrow-stats")]
+    #[test]
+    fn borrow_stats_start_empty() {
+        let counted = cfg!(not(feature = "unchecked"));
+        for _ in 0..100 {
+            let cell = SCell::new(0u32);
+            assert_eq!(cell.borrow_stats(), Default::default());
+            let _first = cell.borrow();
+            let _second = cell.borrow();
+            let stats = cell.borrow_stats();
+            assert_eq!((stats.total(), stats.max_concurrent()), if counted { (2, 2) } else { (0, 0) });
+        }
+    }
+
+    #[cfg(all(feature = "borrow-stats", not(feature = "unchecked")))]
+    #[test]
+    fn borrow_stats_keep_rc_conversions() {
+        let cell = SCell::from(Rc::new(core::cell::RefCell::new(1u32)));
+        *cell.borrow_mut() += 1;
+        assert_eq!(cell.borrow_stats().total(), 1);
+        let rc: Rc<core::cell::RefCell<u32>> = cell.into();
+        assert_eq!(*rc.borrow(), 2);
+    }
+
+    #[test]
+    fn arithmetic_borrows_operands() {
+        let a = SCell::new(6i32);
+        let b = SCell::new(3i32);
+        assert_eq!((&a + &b, &a - &b, &a * &b, &a / &b, -&a), (9, 3, 18, 2, -6));
+        assert_eq!(&a + &a, 12);
+        let held = a.borrow();
+        assert_eq!(&a * &a, *held * *held);
+    }
+
+    #[cfg(all(feature = "async", any(not(feature = "unchecked"), debug_assertions)))]
+    #[test]
+    fn borrow_mut_async_waits_for_guard() {
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::sync::atomic::{AtomicBool, Ordering};
+        use core::task::{Context, Poll, Waker};
+        use std::sync::Arc;
+        use std::task::Wake;
+        struct Flag(AtomicBool);
+        impl Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        let cell = SCell::new(0u32);
+        let mut future = cell.borrow_mut_async();
+        {
+            let _held = cell.borrow();
+            assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+            assert!(!flag.0.load(Ordering::SeqCst));
+        }
+        assert!(flag.0.load(Ordering::SeqCst));
+        match Pin::new(&mut future).poll(&mut cx) {
+            Poll::Ready(mut guard) => *guard += 1,
+            Poll::Pending => panic!("still pending after the guard was dropped"),
+        }
+        assert_eq!(*cell.borrow(), 1);
+    }
+
+    #[cfg(feature = "graph-utils")]
+    #[test]
+    fn detect_cycle_acyclic() {
+        let leaf = SCell::new(Node(Vec::new()));
+        let middle = SCell::new(Node(vec![leaf.clone()]));
+        let root = SCell::new(Node(vec![middle, leaf]));
+        assert!(!root.detect_cycle(children));
+    }
+
+    #[cfg(feature = "graph-utils")]
+    #[test]
+    fn detect_cycle_cyclic() {
+        let leaf = SCell::new(Node(Vec::new()));
+        let root = SCell::new(Node(vec![SCell::new(Node(vec![leaf.clone()]))]));
+        leaf.borrow_mut().0.push(root.clone());
+        assert!(root.detect_cycle(children));
+        assert!(leaf.detect_cycle(children));
+        // Breaks the cycle so the nodes aren't leaked.
+        leaf.borrow_mut().0.clear();
+        assert!(!root.detect_cycle(children));
+    }
+
+    #[cfg(feature = "graph-utils")]
+    #[test]
+    fn drain_unique_skips_shared_nodes() {
+        struct Owned(u32, SCell<Vec<SCell<Owned>>>);
+        let unique = SCell::new(Owned(1, SCell::new(Vec::new())));
+        let shared = SCell::new(Owned(2, SCell::new(Vec::new())));
+        let roots = [SCell::new(Owned(0, SCell::new(vec![unique, shared.clone()])))];
+        let drained = SCell::drain_unique(&roots, |node| node.1.take());
+        assert_eq!(drained.iter().map(|node| node.0).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(shared.strong_count(), 1);
+        assert_eq!(roots[0].borrow().1.borrow().len(), 0);
+    }
+
+    #[cfg(all(feature = "graph-utils", any(not(feature = "unchecked"), debug_assertions)))]
+    #[test]
+    fn fold_graph_skips_borrowed_nodes() {
+        let leaf = SCell::new(Node(Vec::new()));
+        let middle = SCell::new(Node(vec![leaf]));
+        let roots = [SCell::new(Node(vec![middle.clone()]))];
+        let count = |on_borrowed| SCell::fold_graph(&roots, 0, on_borrowed, children, |count, _| count + 1);
+        assert_eq!(count(::OnBorrowed::Skip).ok(), Some(3));
+        let _borrow = middle.borrow_mut();
+        assert_eq!(count(::OnBorrowed::Skip).ok(), Some(1));
+    }
+
+    #[cfg(all(feature = "graph-utils", any(not(feature = "unchecked"), debug_assertions)))]
+    #[test]
+    fn fold_graph_fails_on_borrowed_node() {
+        let middle = SCell::new(Node(vec![SCell::new(Node(Vec::new()))]));
+        let roots = [SCell::new(Node(vec![middle.clone()]))];
+        let count = |on_borrowed| SCell::fold_graph(&roots, 0, on_borrowed, children, |count, _| count + 1);
+        assert_eq!(count(::OnBorrowed::Fail).ok(), Some(3));
+        let _borrow = middle.borrow_mut();
+        assert!(count(::OnBorrowed::Fail).err().unwrap().ptr_eq(&middle));
+    }
+
+    #[cfg(feature = "std")]
+    struct Looped(Option<SCell<Looped>>, bool);
+
+    #[cfg(feature = "std")]
+    impl core::fmt::Display for Looped {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> Result<(), core::fmt::Error> {
+            assert!(!self.1, "formatting panicked");
+            f.write_str("[")?;
+            if let Some(ref next) = self.0 {
+                next.fmt(f)?;
+            }
+            f.write_str("]")
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn display_self_cycle() {
+        use alloc::string::ToString;
+        let cell = SCell::new(Looped(None, false));
+        cell.borrow_mut().0 = Some(cell.clone());
+        assert_eq!(cell.to_string(), "[<cycle>]");
+        assert_eq!(SCell::new(Looped(Some(cell.clone()), false)).to_string(), "[[<cycle>]]");
+        // Breaks the cycle so the cell isn't leaked.
+        cell.borrow_mut().0 = None;
+        assert_eq!(cell.to_string(), "[]");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn display_cycle_state_cleared_after_panic_and_error() {
+        use alloc::string::ToString;
+        use core::fmt::Write;
+        use std::panic::{self, AssertUnwindSafe};
+        struct Failing;
+        impl Write for Failing {
+            fn write_str(&mut self, _: &str) -> Result<(), core::fmt::Error> {
+                Err(core::fmt::Error)
+            }
+        }
+        let formatting = || ::FORMATTING.with(|formatting| formatting.borrow().len());
+        let cell = SCell::new(Looped(None, true));
+        assert!(panic::catch_unwind(AssertUnwindSafe(|| cell.to_string())).is_err());
+        assert_eq!(formatting(), 0);
+        cell.borrow_mut().1 = false;
+        assert!(write!(Failing, "{}", SCell::new(Looped(Some(cell.clone()), false))).is_err());
+        assert_eq!(formatting(), 0);
+        assert_eq!(cell.to_string(), "[]");
+    }
+
+    #[test]
+    fn ref_filter_map() {
+        let cell = SCell::new(vec![1u32, 2]);
+        {
+            let second = ::Ref::filter_map(cell.borrow(), |v| v.get(1)).ok().unwrap();
+            assert_eq!(*second, 2);
+            let all = ::Ref::filter_map(cell.borrow(), |v| v.get(2)).err().unwrap();
+            assert_eq!(*all, [1, 2]);
+            assert_eq!(cell.is_borrowed(), cfg!(any(not(feature = "unchecked"), debug_assertions)));
+            assert!(!cell.is_borrowed_mut());
+        }
+        assert!(!cell.is_borrowed());
+        cell.borrow_mut().push(3);
+    }
+
+    #[test]
+    fn ref_mut_filter_map() {
+        let cell = SCell::new(vec![1u32, 2]);
+        {
+            let mut second = ::RefMut::filter_map(cell.borrow_mut(), |v| v.get_mut(1)).ok().unwrap();
+            *second += 10;
+            assert_eq!(cell.is_borrowed_mut(), cfg!(any(not(feature = "unchecked"), debug_assertions)));
+        }
+        {
+            let mut all = ::RefMut::filter_map(cell.borrow_mut(), |v| v.get_mut(2)).err().unwrap();
+            all.push(3);
+            assert_eq!(cell.is_borrowed_mut(), cfg!(any(not(feature = "unchecked"), debug_assertions)));
+        }
+        assert!(!cell.is_borrowed());
+        assert_eq!(*cell.borrow(), [1, 12, 3]);
+    }
+
+    #[test]
+    fn ref_mut_try_map() {
+        let cell = SCell::new(vec![1u32, 2]);
+        {
+            let mut first = ::RefMut::try_map(cell.borrow_mut(), |v| v.first_mut().ok_or("empty")).ok().unwrap();
+            *first += 10;
+            assert_eq!(cell.is_borrowed_mut(), cfg!(any(not(feature = "unchecked"), debug_assertions)));
+        }
+        {
+            let (mut all, e) = ::RefMut::try_map(cell.borrow_mut(), |v| v.get_mut(5).ok_or("out of range")).err().unwrap();
+            assert_eq!(e, "out of range");
+            all.push(3);
+            assert_eq!(cell.is_borrowed_mut(), cfg!(any(not(feature = "unchecked"), debug_assertions)));
+        }
+        assert!(!cell.is_borrowed());
+        assert_eq!(*cell.borrow(), [11, 2, 3]);
+    }
+
+    #[cfg(all(feature = "unchecked", debug_assertions))]
+    #[test]
+    #[should_panic(expected = "already mutably borrowed: another RefMut to this SCell is alive")]
+    fn unchecked_debug_double_borrow_mut_panics() {
+        let cell = SCell::new(0u32);
+        let _first = cell.borrow_mut();
+        let _second = cell.borrow_mut();
+    }
+
+    #[cfg(all(feature = "unchecked", debug_assertions))]
+    #[test]
+    #[should_panic(expected = "already mutably borrowed")]
+    fn unchecked_debug_borrow_while_mutably_borrowed_panics() {
+        let cell = SCell::new(0u32);
+        let _first = cell.borrow_mut();
+        let _second = cell.borrow();
+    }
+
+    #[test]
+    fn extend_owned_string() {
+        let mut cell = SCell::new(String::from("a"));
+        cell.extend(['b', 'c']);
+        cell.extend(["de", "f"]);
+        (&cell).extend(['g']);
+        assert_eq!(*cell.borrow(), "abcdefg");
+    }
+
+    #[test]
+    fn swap_with_itself_does_nothing() {
+        let a = SCell::new(1u32);
+        let b = SCell::new(2u32);
+        a.swap(&a.clone());
+        {
+            let _held = a.borrow();
+            a.swap(&a);
+        }
+        a.swap(&b);
+        assert_eq!((*a.borrow(), *b.borrow()), (2, 1));
+    }
+
+    #[test]
+    fn adopt_detaches_from_old_parent() {
+        use Parented;
+        let first = SCell::new(Parented::new(1));
+        let second = SCell::new(Parented::new(2));
+        let child = SCell::new(Parented::new(3));
+        Parented::adopt(&first, child.clone());
+        Parented::adopt(&first, child.clone());
+        assert_eq!(first.borrow().children().len(), 1);
+        Parented::adopt(&second, child.clone());
+        assert!(first.borrow().children().is_empty());
+        assert!(second.borrow().children()[0].ptr_eq(&child));
+        assert!(child.borrow().parent().unwrap().ptr_eq(&second));
     }
 }
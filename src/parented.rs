@@ -0,0 +1,88 @@
+use alloc::vec::Vec;
+use {SCell, SWeak};
+#[cfg(feature = "graph-utils")]
+use GraphNode;
+
+/// A tree node holding a value along with strong handles to its children and a weak handle to its parent
+///
+/// Children are owned by their parent, but a parent isn't owned by its children, so dropping the last handle to the
+/// root drops the whole tree instead of leaking it through the parent pointers.
+#[derive(Default)]
+pub struct Parented<T> {
+    pub value: T,
+    parent: SWeak<Parented<T>>,
+    children: Vec<SCell<Parented<T>>>,
+}
+
+impl<T> Parented<T> {
+    /// Creates a node with no parent or children
+    #[inline]
+    pub fn new(value: T) -> Self {
+        Parented {
+            value,
+            parent: SWeak::new(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Gets the parent, or `None` if there is none or it has been dropped
+    #[inline]
+    pub fn parent(&self) -> Option<SCell<Parented<T>>> {
+        self.parent.upgrade()
+    }
+
+    /// Sets the parent without adding this node to its children
+    #[inline]
+    pub fn set_parent(&mut self, parent: &SCell<Parented<T>>) {
+        self.parent = parent.downgrade();
+    }
+
+    /// Removes the parent without removing this node from its children
+    #[inline]
+    pub fn clear_parent(&mut self) {
+        self.parent = SWeak::new();
+    }
+
+    #[inline]
+    pub fn children(&self) -> &[SCell<Parented<T>>] {
+        &self.children
+    }
+
+    /// Adds `child` to the children of `parent` and makes `parent` its parent
+    ///
+    /// If `child` already has a parent, it is removed from that parent's children first, so it ends up last among the
+    /// children of `parent` even if `parent` already had it. Panics if any of the nodes are borrowed or if `parent` and
+    /// `child` are the same node.
+    #[track_caller]
+    pub fn adopt(parent: &SCell<Parented<T>>, child: SCell<Parented<T>>) {
+        assert!(!parent.ptr_eq(&child), "a node can't adopt itself");
+        let old_parent = child.borrow().parent();
+        if let Some(old_parent) = old_parent {
+            old_parent.borrow_mut().children.retain(|sibling| !sibling.ptr_eq(&child));
+        }
+        child.borrow_mut().set_parent(parent);
+        parent.borrow_mut().children.push(child);
+    }
+
+    /// Removes and returns the child at `index`, clearing its parent
+    ///
+    /// Panics if `index` is out of bounds or the child is borrowed.
+    pub fn remove_child(&mut self, index: usize) -> SCell<Parented<T>> {
+        let child = self.children.remove(index);
+        child.borrow_mut().clear_parent();
+        child
+    }
+}
+
+#[cfg(feature = "graph-utils")]
+impl<T> GraphNode for Parented<T> {
+    #[inline]
+    fn add_child(&mut self, child: SCell<Self>) {
+        self.children.push(child);
+    }
+
+    #[inline]
+    fn set_parent(&mut self, parent: SWeak<Self>) {
+        self.parent = parent;
+    }
+}
@@ -0,0 +1,27 @@
+//! Each `SCell` serializes its contents independently, so handles that shared an instance before serialization will
+//! refer to separate instances after deserialization.
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use SCell;
+
+impl<T: ?Sized> Serialize for SCell<T>
+    where T: Serialize
+{
+    #[inline]
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        self.borrow().serialize(serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for SCell<T>
+    where T: Deserialize<'de>
+{
+    #[inline]
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        T::deserialize(deserializer).map(SCell::new)
+    }
+}
@@ -0,0 +1,154 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError};
+use std::time::{Duration, Instant};
+use std::thread;
+use std::ops::{Deref, DerefMut};
+use std::fmt::{Formatter, Debug, Error, Pointer};
+
+/// A thread-safe smart container for objects in recursive data structures
+///
+/// This container contains Arc and therefore `clone()` will create a new reference to the same instance.
+///
+/// Lock poisoning is ignored: if a thread panics while holding a guard, later borrows still hand out the contents as if
+/// they were valid, even though the panic may have left them half-updated. Callers that need to know must keep their
+/// own record of whether an update finished.
+#[derive(Default)]
+pub struct ASCell<T: ?Sized>(Arc<Mutex<T>>);
+
+/// A lock guard wrapper that lets rust make the same guarantees regardless of internal type
+pub struct ARef<'a, T: 'a + ?Sized>(MutexGuard<'a, T>);
+
+/// A mutable lock guard wrapper that lets rust make the same guarantees regardless of internal type
+pub struct ARefMut<'a, T: 'a + ?Sized>(MutexGuard<'a, T>);
+
+impl<T> ASCell<T> {
+    #[inline]
+    pub fn new(t: T) -> Self {
+        ASCell(Arc::new(Mutex::new(t)))
+    }
+}
+
+impl<T: ?Sized> ASCell<T> {
+    /// Locks the contents for reading, blocking the current thread until they are available
+    ///
+    /// A poisoned lock is ignored since the unchecked backend has no way to report poisoning.
+    #[inline]
+    pub fn borrow(&self) -> ARef<'_, T> {
+        ARef(self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Locks the contents for writing, blocking the current thread until they are available
+    ///
+    /// A poisoned lock is ignored since the unchecked backend has no way to report poisoning.
+    #[inline]
+    pub fn borrow_mut(&self) -> ARefMut<'_, T> {
+        ARefMut(self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Locks the contents for writing, or returns `None` if they are still locked after `dur`
+    ///
+    /// `Mutex` has no timed lock, so this polls `try_lock`. It yields the thread for the first few attempts, then
+    /// sleeps for a delay that starts at 10 microseconds and doubles each attempt up to 1 millisecond, never sleeping
+    /// past the deadline. A poisoned lock is ignored like in `borrow_mut`.
+    pub fn try_borrow_mut_for(&self, dur: Duration) -> Option<ARefMut<'_, T>> {
+        let deadline = Instant::now() + dur;
+        let mut delay = Duration::from_micros(10);
+        let mut attempt = 0;
+        loop {
+            match self.0.try_lock() {
+                Ok(guard) => return Some(ARefMut(guard)),
+                Err(TryLockError::Poisoned(e)) => return Some(ARefMut(e.into_inner())),
+                Err(TryLockError::WouldBlock) => {}
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            if attempt < 4 {
+                thread::yield_now();
+            } else {
+                thread::sleep(delay.min(deadline - now));
+                delay = (delay * 2).min(Duration::from_millis(1));
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Locks every cell in `cells` for writing, blocking the current thread until they are all available
+    ///
+    /// The locks are always taken in order of address, whatever order `cells` is in, so two threads locking
+    /// overlapping sets of cells with this can't deadlock each other. The guards are returned in the order of `cells`.
+    /// Panics if the same instance appears twice, since locking it again would deadlock.
+    pub fn borrow_mut_all<'a>(cells: &[&'a ASCell<T>]) -> Vec<ARefMut<'a, T>> {
+        let mut order: Vec<usize> = (0..cells.len()).collect();
+        order.sort_by_key(|&i| Arc::as_ptr(&cells[i].0) as *const ());
+        assert!(order.windows(2).all(|w| !cells[w[0]].ptr_eq(cells[w[1]])), "cannot lock an ASCell twice");
+        let mut guards: Vec<Option<ARefMut<'a, T>>> = cells.iter().map(|_| None).collect();
+        for i in order {
+            guards[i] = Some(cells[i].borrow_mut());
+        }
+        guards.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// Checks if two `ASCell` handles refer to the same instance without locking the contents
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T: ?Sized> Clone for ASCell<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        ASCell(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> Pointer for ASCell<T> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        Pointer::fmt(&self.0, f)
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Deref for ARef<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Debug for ARef<'a, T>
+    where T: Debug
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        (*self.0).fmt(f)
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Deref for ARefMut<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<'a, T: 'a + ?Sized> DerefMut for ARefMut<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Debug for ARefMut<'a, T>
+    where T: Debug
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        (*self.0).fmt(f)
+    }
+}
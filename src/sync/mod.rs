@@ -0,0 +1,27 @@
+#[cfg(not(feature = "unchecked"))]
+mod checked;
+#[cfg(not(feature = "unchecked"))]
+pub use self::checked::*;
+
+#[cfg(feature = "unchecked")]
+mod unchecked;
+#[cfg(feature = "unchecked")]
+pub use self::unchecked::*;
+
+use std::fmt::{Formatter, Debug, Error};
+
+impl<T: ?Sized> Debug for ASCell<T>
+    where T: Debug
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        self.borrow().fmt(f)
+    }
+}
+
+impl<T> From<T> for ASCell<T> {
+    #[inline]
+    fn from(t: T) -> Self {
+        ASCell::new(t)
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::fmt::{Formatter, Debug, Error, Pointer};
+use std::time::{Duration, Instant};
+use std::{hint, thread};
+
+/// A thread-safe smart container for objects in recursive data structures
+///
+/// This container contains Arc and therefore `clone()` will create a new reference to the same instance.
+///
+/// The unchecked backend guards the contents with a spin lock instead of a `Mutex`, so code that is correct with the
+/// checked backend stays free of data races, but a thread waiting for the contents burns its time slice.
+#[derive(Default)]
+pub struct ASCell<T: ?Sized>(Arc<Inner<T>>);
+
+/// The allocation shared by `ASCell` handles
+#[derive(Default)]
+struct Inner<T: ?Sized> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+/// The contents are only reached while the lock is held, like with `Mutex`.
+unsafe impl<T: ?Sized + Send> Send for ASCell<T> {}
+unsafe impl<T: ?Sized + Send> Sync for ASCell<T> {}
+
+/// Holds the lock of an `ASCell` until it is dropped
+struct Lock<'a>(&'a AtomicBool);
+
+/// A lock guard wrapper that lets rust make the same guarantees regardless of internal type
+pub struct ARef<'a, T: 'a + ?Sized> {
+    value: &'a T,
+    _lock: Lock<'a>,
+}
+
+/// A mutable lock guard wrapper that lets rust make the same guarantees regardless of internal type
+pub struct ARefMut<'a, T: 'a + ?Sized> {
+    value: &'a mut T,
+    _lock: Lock<'a>,
+}
+
+impl<'a> Lock<'a> {
+    #[inline]
+    fn try_new(locked: &'a AtomicBool) -> Option<Self> {
+        match locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => Some(Lock(locked)),
+            Err(_) => None,
+        }
+    }
+
+    #[inline]
+    fn new(locked: &'a AtomicBool) -> Self {
+        loop {
+            if let Some(lock) = Lock::try_new(locked) {
+                return lock;
+            }
+            while locked.load(Ordering::Relaxed) {
+                hint::spin_loop();
+            }
+        }
+    }
+}
+
+impl<'a> Drop for Lock<'a> {
+    #[inline]
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl<T> ASCell<T> {
+    #[inline]
+    pub fn new(t: T) -> Self {
+        ASCell(Arc::new(Inner {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(t),
+        }))
+    }
+}
+
+impl<T: ?Sized> ASCell<T> {
+    /// Locks the contents for reading, spinning until they are available
+    #[inline]
+    pub fn borrow(&self) -> ARef<'_, T> {
+        let lock = Lock::new(&self.0.locked);
+        ARef { value: unsafe{&*self.0.value.get() as &T}, _lock: lock }
+    }
+
+    /// Locks the contents for writing, spinning until they are available
+    #[inline]
+    pub fn borrow_mut(&self) -> ARefMut<'_, T> {
+        let lock = Lock::new(&self.0.locked);
+        ARefMut { value: unsafe{&mut *self.0.value.get() as &mut T}, _lock: lock }
+    }
+
+    /// Locks the contents for writing, or returns `None` if they are still locked after `dur`
+    ///
+    /// This spins, yielding the thread between attempts, and never waits past the deadline.
+    pub fn try_borrow_mut_for(&self, dur: Duration) -> Option<ARefMut<'_, T>> {
+        let deadline = Instant::now() + dur;
+        loop {
+            if let Some(lock) = Lock::try_new(&self.0.locked) {
+                return Some(ARefMut { value: unsafe{&mut *self.0.value.get() as &mut T}, _lock: lock });
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            thread::yield_now();
+        }
+    }
+
+    /// Locks every cell in `cells` for writing, spinning until they are all available
+    ///
+    /// The locks are always taken in order of address, whatever order `cells` is in, so two threads locking
+    /// overlapping sets of cells with this can't deadlock each other. The guards are returned in the order of `cells`.
+    /// Panics if the same instance appears twice, since locking it again would deadlock.
+    pub fn borrow_mut_all<'a>(cells: &[&'a ASCell<T>]) -> Vec<ARefMut<'a, T>> {
+        let mut order: Vec<usize> = (0..cells.len()).collect();
+        order.sort_by_key(|&i| Arc::as_ptr(&cells[i].0) as *const ());
+        assert!(order.windows(2).all(|w| !cells[w[0]].ptr_eq(cells[w[1]])), "cannot lock an ASCell twice");
+        let mut guards: Vec<Option<ARefMut<'a, T>>> = cells.iter().map(|_| None).collect();
+        for i in order {
+            guards[i] = Some(cells[i].borrow_mut());
+        }
+        guards.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// Checks if two `ASCell` handles refer to the same instance without locking the contents
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T: ?Sized> Clone for ASCell<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        ASCell(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> Pointer for ASCell<T> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        Pointer::fmt(&self.0, f)
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Deref for ARef<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Debug for ARef<'a, T>
+    where T: Debug
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        (*self.value).fmt(f)
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Deref for ARefMut<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &*self.value
+    }
+}
+
+impl<'a, T: 'a + ?Sized> DerefMut for ARefMut<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut *self.value
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Debug for ARefMut<'a, T>
+    where T: Debug
+{
+    #[inline]
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        (*self.value).fmt(f)
+    }
+}
@@ -0,0 +1,53 @@
+//! A registry of tracked allocations, enabled by the `tracked` feature.
+//!
+//! Each thread has its own registry, holding a list of weak handles for every type that has been tracked on it.
+
+use core::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use {SCell, SWeak};
+
+thread_local!(static REGISTRY: RefCell<HashMap<TypeId, Box<dyn Any>>> = RefCell::new(HashMap::new()));
+
+/// Runs `f` on the list of weak handles for `T` on this thread
+fn with_registry<T: 'static, R, F>(f: F) -> R
+    where F: FnOnce(&mut Vec<SWeak<T>>) -> R
+{
+    REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        let handles = registry.entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Vec::<SWeak<T>>::new()))
+            .downcast_mut::<Vec<SWeak<T>>>()
+            .unwrap();
+        f(handles)
+    })
+}
+
+impl<T: 'static> SCell<T> {
+    /// Constructs a new `SCell` and registers it so that it is returned by `SCell::live_handles` while it is alive
+    ///
+    /// The registry is thread-local and only holds a weak handle, so it doesn't keep the contents alive. Registering
+    /// is much slower than `new`, so this is intended for tracking down leaks.
+    pub fn new_tracked(t: T) -> Self {
+        let cell = SCell::new(t);
+        with_registry(|handles| {
+            // Pruning before the list would grow keeps it from filling up with dead handles without making every call
+            // walk the whole list.
+            if handles.len() == handles.capacity() {
+                handles.retain(|handle| handle.upgrade().is_some());
+            }
+            handles.push(cell.downgrade());
+        });
+        cell
+    }
+
+    /// Gets a handle to every instance of `SCell<T>` created with `new_tracked` on this thread that is still alive
+    pub fn live_handles() -> Vec<SCell<T>> {
+        with_registry(|handles: &mut Vec<SWeak<T>>| {
+            handles.retain(|handle| handle.upgrade().is_some());
+            handles.iter().filter_map(SWeak::upgrade).collect()
+        })
+    }
+}
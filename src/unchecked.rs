@@ -1,37 +1,557 @@
-use std::rc::Rc;
-use std::cell::UnsafeCell;
-use std::ops::{Deref, DerefMut};
-use std::fmt::{Formatter, Debug, Error, Pointer};
-use std::cmp::Ordering;
+use alloc::rc::{Rc, Weak};
+use core::cell::{RefCell, UnsafeCell};
+use core::ops::{Deref, DerefMut};
+use core::fmt::{Formatter, Debug, Display, Error, Pointer};
+use core::any::Any;
+use core::mem;
+use core::error;
+use core::convert::TryFrom;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ptr;
+use core::ffi::c_void;
+#[cfg(feature = "track-borrows")]
+use core::convert::Infallible;
+#[cfg(feature = "track-borrows")]
+use core::panic::Location;
+#[cfg(debug_assertions)]
+use core::cell::Cell;
+#[cfg(not(debug_assertions))]
+use core::marker::PhantomData;
 
 /// A smart container for objects in recursive data structures
 ///
 /// This container contains Rc and therefore `clone()` will create a new reference to the same instance.
 #[derive(Default)]
-pub struct SCell<T: ?Sized>(Rc<UnsafeCell<T>>);
+pub struct SCell<T: ?Sized>(Rc<Inner<T>>);
+
+/// With the `force-send` feature, `SCell` is `Send` and `Sync` when `T: Send`.
+///
+/// This is wildly unsafe and only exists for runtimes that move data between threads while guaranteeing that no two
+/// threads ever touch it at the same time. Nothing here is atomic: cloning or dropping handles to the same instance on
+/// two threads at once corrupts the reference count, and so does borrowing in debug builds, where the borrow flag is a
+/// plain `Cell`. `SWeak` and the guards stay `!Send` and `!Sync`, so only whole `SCell` handles can cross threads.
+#[cfg(feature = "force-send")]
+unsafe impl<T: ?Sized> Send for SCell<T> where T: Send {}
+
+#[cfg(feature = "force-send")]
+unsafe impl<T: ?Sized> Sync for SCell<T> where T: Send {}
+
+/// A weak reference to the contents of an `SCell`
+///
+/// This does not keep the contents alive, so it must be upgraded to an `SCell` before it can be borrowed.
+pub struct SWeak<T: ?Sized>(Weak<Inner<T>>);
 
 /// A reference wrapper that lets rust make the same guarantees regardless of internal type
-pub struct Ref<'a, T: 'a + ?Sized>(&'a T);
+pub struct Ref<'a, T: 'a + ?Sized>(&'a T, BorrowRef<'a>);
 
 /// A mutable reference wrapper that lets rust make the same guarantees regardless of internal type
-pub struct RefMut<'a, T: 'a + ?Sized>(&'a mut T);
+pub struct RefMut<'a, T: 'a + ?Sized>(&'a mut T, BorrowRefMut<'a>);
+
+/// An error returned by `SCell::try_borrow`
+///
+/// The unchecked backend only tracks borrows when `debug_assertions` are enabled, so this is never returned in release.
+#[derive(Debug)]
+pub struct BorrowError {
+    _private: (),
+}
+
+/// An error returned by `SCell::try_borrow_mut`
+///
+/// The unchecked backend only tracks borrows when `debug_assertions` are enabled, so this is never returned in release.
+#[derive(Debug)]
+pub struct BorrowMutError {
+    _private: (),
+}
+
+/// The error returned by `SCell::try_borrow_mut`, under the name the checked backend also uses
+pub type BorrowConflict = BorrowMutError;
+
+/// How an instance has been borrowed over its lifetime, returned by `SCell::borrow_stats`
+///
+/// The unchecked backend doesn't count borrows, so this is always empty. It only exists so that code using the
+/// `borrow-stats` feature builds with either backend.
+#[cfg(feature = "borrow-stats")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BorrowStats {
+    _private: (),
+}
+
+#[cfg(feature = "borrow-stats")]
+impl BorrowStats {
+    /// Gets the number of guards that have been created, which is always 0 with the unchecked backend
+    #[inline]
+    pub fn total(&self) -> usize {
+        0
+    }
+
+    /// Gets the most guards that have been alive at the same time, which is always 0 with the unchecked backend
+    #[inline]
+    pub fn max_concurrent(&self) -> usize {
+        0
+    }
+}
+
+/// Which kind of borrow failed, reported by `BorrowFailure::kind`
+#[cfg(feature = "track-borrows")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BorrowKind {
+    /// A `borrow`, which conflicted with a mutable borrow
+    Shared,
+    /// A `borrow_mut`, which conflicted with any borrow
+    Mutable,
+}
+
+/// A borrow that is about to panic, passed to the hook set with `set_borrow_panic_hook`
+///
+/// The unchecked backend never calls the hook, so this can't be constructed. It only exists so that code using the
+/// `track-borrows` feature builds with either backend.
+#[cfg(feature = "track-borrows")]
+#[derive(Debug)]
+pub struct BorrowFailure {
+    never: Infallible,
+}
+
+#[cfg(feature = "track-borrows")]
+impl BorrowFailure {
+    /// Gets which kind of borrow failed
+    #[inline]
+    pub fn kind(&self) -> BorrowKind {
+        match self.never {}
+    }
+
+    /// Gets where the borrow that failed was attempted
+    #[inline]
+    pub fn attempted(&self) -> &'static Location<'static> {
+        match self.never {}
+    }
+
+    /// Gets where the outstanding borrows that it conflicted with were taken
+    #[inline]
+    pub fn held(&self) -> &[&'static Location<'static>] {
+        match self.never {}
+    }
+}
+
+/// Sets a function to call right before `borrow` or `borrow_mut` panics because of a conflict, which does nothing with
+/// the unchecked backend
+///
+/// The unchecked backend doesn't record where borrows are held, so it never calls the hook, even when a debug build
+/// panics on a conflict.
+#[cfg(feature = "track-borrows")]
+#[inline]
+pub fn set_borrow_panic_hook(hook: fn(&BorrowFailure)) {
+    let _ = hook;
+}
+
+/// The allocation shared by `SCell` handles
+///
+/// The borrow flag is only present when `debug_assertions` are enabled, so in release this is just the `UnsafeCell`.
+/// Like `RefCell`, a positive flag counts shared borrows and a negative flag counts mutable borrows.
+#[derive(Default)]
+struct Inner<T: ?Sized> {
+    #[cfg(debug_assertions)]
+    borrow: Cell<isize>,
+    value: UnsafeCell<T>,
+}
+
+#[cfg(debug_assertions)]
+struct BorrowRef<'a>(&'a Cell<isize>);
+
+#[cfg(not(debug_assertions))]
+struct BorrowRef<'a>(PhantomData<&'a ()>);
+
+#[cfg(debug_assertions)]
+struct BorrowRefMut<'a>(&'a Cell<isize>);
+
+#[cfg(not(debug_assertions))]
+struct BorrowRefMut<'a>(PhantomData<&'a ()>);
+
+impl<T> Inner<T> {
+    #[inline]
+    fn new(t: T) -> Self {
+        Inner {
+            #[cfg(debug_assertions)]
+            borrow: Cell::new(0),
+            value: UnsafeCell::new(t),
+        }
+    }
+}
+
+impl<'a> BorrowRef<'a> {
+    #[cfg(debug_assertions)]
+    #[inline]
+    fn new<T: ?Sized>(inner: &'a Inner<T>) -> Option<Self> {
+        let borrow = inner.borrow.get();
+        if borrow < 0 {
+            None
+        } else {
+            inner.borrow.set(borrow + 1);
+            Some(BorrowRef(&inner.borrow))
+        }
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[inline]
+    fn new<T: ?Sized>(_: &'a Inner<T>) -> Option<Self> {
+        Some(BorrowRef(PhantomData))
+    }
+}
+
+impl<'a> Clone for BorrowRef<'a> {
+    #[cfg(debug_assertions)]
+    #[inline]
+    fn clone(&self) -> Self {
+        self.0.set(self.0.get() + 1);
+        BorrowRef(self.0)
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[inline]
+    fn clone(&self) -> Self {
+        BorrowRef(PhantomData)
+    }
+}
+
+#[cfg(debug_assertions)]
+impl<'a> Drop for BorrowRef<'a> {
+    #[inline]
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+        #[cfg(feature = "async")]
+        ::waiters::wake(self.0 as *const Cell<isize> as *const ());
+    }
+}
+
+impl<'a> BorrowRefMut<'a> {
+    #[cfg(debug_assertions)]
+    #[inline]
+    fn new<T: ?Sized>(inner: &'a Inner<T>) -> Option<Self> {
+        if inner.borrow.get() != 0 {
+            None
+        } else {
+            inner.borrow.set(-1);
+            Some(BorrowRefMut(&inner.borrow))
+        }
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[inline]
+    fn new<T: ?Sized>(_: &'a Inner<T>) -> Option<Self> {
+        Some(BorrowRefMut(PhantomData))
+    }
+}
+
+/// Only used to split a mutable borrow into disjoint parts, so it counts as another mutable borrow.
+impl<'a> Clone for BorrowRefMut<'a> {
+    #[cfg(debug_assertions)]
+    #[inline]
+    fn clone(&self) -> Self {
+        self.0.set(self.0.get() - 1);
+        BorrowRefMut(self.0)
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[inline]
+    fn clone(&self) -> Self {
+        BorrowRefMut(PhantomData)
+    }
+}
+
+#[cfg(debug_assertions)]
+impl<'a> Drop for BorrowRefMut<'a> {
+    #[inline]
+    fn drop(&mut self) {
+        self.0.set(self.0.get() + 1);
+        #[cfg(feature = "async")]
+        ::waiters::wake(self.0 as *const Cell<isize> as *const ());
+    }
+}
 
 impl<T> SCell<T> {
     #[inline]
     pub fn new(t: T) -> Self {
-        SCell(Rc::new(UnsafeCell::new(t)))
+        SCell(Rc::new(Inner::new(t)))
+    }
+
+    /// Constructs a new `SCell` from the value `f` returns, giving it an `SWeak` to the instance being constructed
+    ///
+    /// The `SWeak` can't be upgraded until this returns, but it can be stored in the value to build cycles.
+    #[inline]
+    pub fn new_cyclic<F>(f: F) -> Self
+        where F: FnOnce(&SWeak<T>) -> T
+    {
+        SCell(Rc::new_cyclic(|weak| Inner::new(f(&SWeak(weak.clone())))))
+    }
+
+    /// Replaces the contents with `t` and returns the old value
+    ///
+    /// Calling this while the contents are borrowed is undefined behavior, although debug builds will panic instead.
+    #[inline]
+    pub fn replace(&self, t: T) -> T {
+        mem::replace(&mut *self.borrow_mut(), t)
+    }
+
+    /// Replaces the contents with the value computed by `f` and returns the old value
+    ///
+    /// Calling this while the contents are borrowed is undefined behavior, although debug builds will panic instead.
+    #[inline]
+    pub fn replace_with<F>(&self, f: F) -> T
+        where F: FnOnce(&mut T) -> T
+    {
+        let mut_borrow = &mut *self.borrow_mut();
+        let replacement = f(mut_borrow);
+        mem::replace(mut_borrow, replacement)
+    }
+
+    /// Swaps the contents of two `SCell` instances
+    ///
+    /// Swapping an instance with itself does nothing, even while it is borrowed, like with the checked backend.
+    /// Otherwise calling this while either is borrowed is undefined behavior, although debug builds will panic instead.
+    #[inline]
+    #[track_caller]
+    pub fn swap(&self, other: &Self) {
+        if !self.ptr_eq(other) {
+            mem::swap(&mut *self.borrow_mut(), &mut *other.borrow_mut())
+        }
+    }
+
+    /// Returns the contents if this is the only `SCell` handle to them, otherwise returns the handle
+    #[inline]
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        Rc::try_unwrap(this.0).map(|inner| inner.value.into_inner()).map_err(SCell)
+    }
+
+    /// Returns the contents, consuming this handle
+    ///
+    /// Panics with the current strong count if there are other `SCell` handles to the contents.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        match Rc::try_unwrap(self.0) {
+            Ok(inner) => inner.value.into_inner(),
+            Err(rc) => panic!("cannot take the contents of an SCell with {} strong handles", Rc::strong_count(&rc)),
+        }
+    }
+
+    /// Moves the contents into a new `Rc<RefCell<T>>` so they can be passed to code expecting the checked layout, or
+    /// returns the handle if there are other `SCell` handles to them
+    ///
+    /// This reallocates the contents, which is a one-time cost of a move. The checked backend never fails here, since
+    /// it only unwraps the `Rc` it is built on.
+    #[inline]
+    pub fn try_into_rc(self) -> Result<Rc<RefCell<T>>, Self> {
+        SCell::try_unwrap(self).map(|t| Rc::new(RefCell::new(t)))
+    }
+
+    /// Consumes the handle, returning an opaque pointer that keeps the instance alive until it is passed to `from_raw`
+    ///
+    /// The pointer identifies the allocation, which starts with the cell's bookkeeping rather than the contents, so it
+    /// is a `c_void` that can't be dereferenced. Use `as_ptr` to reach the contents instead. The pointer is thin, so it
+    /// can't carry the length or vtable that unsized contents would need.
+    #[inline]
+    pub fn into_raw(this: Self) -> *const c_void {
+        Rc::into_raw(this.0) as *const c_void
+    }
+
+    /// Reconstructs a handle from a pointer returned by `into_raw`
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `into_raw` on an `SCell<T>` of the same `T` built with the same backend, and
+    /// each pointer returned by `into_raw` must be passed to this exactly once. Passing it more often drops the
+    /// instance while other handles still refer to it, and never passing it leaks the instance.
+    #[inline]
+    pub unsafe fn from_raw(ptr: *const c_void) -> Self {
+        SCell(Rc::from_raw(ptr as *const Inner<T>))
     }
 }
 
 impl<T: ?Sized> SCell<T> {
+    /// Borrows the contents
+    ///
+    /// Debug builds panic if the contents are mutably borrowed, but release builds don't check anything.
     #[inline]
-    pub fn borrow(&self) -> Ref<T> {
-        Ref(unsafe{&*self.0.get() as &T})
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.try_borrow().expect("already mutably borrowed")
     }
 
+    /// Mutably borrows the contents
+    ///
+    /// Debug builds panic if the contents are borrowed, but release builds don't check anything. A second mutable
+    /// borrow gets its own message, since in release it would silently alias the first.
     #[inline]
-    pub fn borrow_mut(&self) -> RefMut<T> {
-        RefMut(unsafe{&mut *self.0.get() as &mut T})
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        match self.try_borrow_mut() {
+            Ok(borrow) => borrow,
+            Err(_) if self.borrow_state() == ::BorrowState::Mutable => panic!(
+                "already mutably borrowed: another RefMut to this SCell is alive, which is undefined behavior in release"),
+            Err(_) => panic!("already borrowed"),
+        }
+    }
+
+    #[inline]
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
+        match BorrowRef::new(&self.0) {
+            Some(borrow) => Ok(Ref(unsafe{&*self.0.value.get() as &T}, borrow)),
+            None => Err(BorrowError { _private: () }),
+        }
+    }
+
+    #[inline]
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, BorrowMutError> {
+        match BorrowRefMut::new(&self.0) {
+            Some(borrow) => Ok(RefMut(unsafe{&mut *self.0.value.get() as &mut T}, borrow)),
+            None => Err(BorrowMutError { _private: () }),
+        }
+    }
+
+    /// Borrows the contents for a check that doesn't hand the borrow out, which the checked backend doesn't count
+    ///
+    /// In release this always succeeds, so callers must first check `borrow_state` to avoid aliasing a `RefMut`.
+    #[inline]
+    pub(crate) fn try_peek(&self) -> Result<Ref<'_, T>, BorrowError> {
+        self.try_borrow()
+    }
+
+    /// Gets how this instance has been borrowed since it was created, which is never counted with the unchecked backend
+    #[cfg(feature = "borrow-stats")]
+    #[inline]
+    pub fn borrow_stats(&self) -> BorrowStats {
+        BorrowStats::default()
+    }
+
+    /// Gets how the contents are borrowed right now by reading the borrow flag, without making a reference to them
+    ///
+    /// Release builds have no flag, so this is always `Untracked` there. Borrowing to find out would make a reference
+    /// that aliases any live guard, which is undefined behavior.
+    #[inline]
+    pub(crate) fn borrow_state(&self) -> ::BorrowState {
+        #[cfg(debug_assertions)]
+        let state = match self.0.borrow.get() {
+            0 => ::BorrowState::Unused,
+            borrow if borrow > 0 => ::BorrowState::Shared,
+            _ => ::BorrowState::Mutable,
+        };
+        #[cfg(not(debug_assertions))]
+        let state = ::BorrowState::Untracked;
+        state
+    }
+
+    #[inline]
+    pub fn downgrade(&self) -> SWeak<T> {
+        SWeak(Rc::downgrade(&self.0))
+    }
+
+    /// Gets the number of `SCell` handles to this instance without borrowing the contents
+    #[inline]
+    pub fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+
+    /// Gets the number of `SWeak` handles to this instance without borrowing the contents
+    #[inline]
+    pub fn weak_count(&self) -> usize {
+        Rc::weak_count(&self.0)
+    }
+
+    /// Gets the number of `SCell` and `SWeak` handles to this instance, in that order, without borrowing the contents
+    #[inline]
+    pub fn ref_counts(&self) -> (usize, usize) {
+        (Rc::strong_count(&self.0), Rc::weak_count(&self.0))
+    }
+
+    /// Checks if two `SCell` handles refer to the same instance without borrowing the contents
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Mutably borrows the contents without any runtime checks if this is the only handle to them
+    ///
+    /// Returns `None` if there are other `SCell` or `SWeak` handles to the contents.
+    #[inline]
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        Rc::get_mut(&mut self.0).map(|inner| inner.value.get_mut())
+    }
+
+    /// Identifies this instance to the tasks waiting in `borrow_mut_async`, matching the key the guards wake on drop
+    #[cfg(feature = "async")]
+    #[inline]
+    pub(crate) fn wait_key(&self) -> *const () {
+        #[cfg(debug_assertions)]
+        let key = &self.0.borrow as *const Cell<isize> as *const ();
+        #[cfg(not(debug_assertions))]
+        let key = self.as_ptr() as *const ();
+        key
+    }
+
+    /// Gets a raw pointer to the contents without borrowing them
+    ///
+    /// Dereferencing the pointer bypasses the borrow rules this type otherwise upholds, so it is up to the caller to
+    /// ensure it isn't used to alias a live `Ref` or `RefMut`.
+    #[inline]
+    pub fn as_ptr(&self) -> *mut T {
+        self.0.value.get()
+    }
+
+    /// Gets a mutable reference to the contents without going through a `RefMut`
+    ///
+    /// This skips the borrow flag entirely, even in debug builds, so only use it in code that is too hot for a guard.
+    /// It only exists with the `unchecked` feature.
+    ///
+    /// # Safety
+    ///
+    /// No other reference to the contents, including a `Ref` or `RefMut` from any handle, may be used while the
+    /// returned reference is alive.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_mut_unchecked(&self) -> &mut T {
+        &mut *self.0.value.get()
+    }
+}
+
+impl<T> SCell<[T]> {
+    /// Moves the elements of `v` into a new `SCell` holding a slice
+    ///
+    /// The length is fixed once the instance is built, since it is stored in each handle rather than in the instance,
+    /// so use an `SCell<Vec<T>>` for contents that need to grow or shrink. The elements are moved into a `Box` that is
+    /// then moved into the `Rc`, so this costs two copies of the `Vec`'s contents.
+    pub fn from_vec(v: Vec<T>) -> Self {
+        let len = v.len();
+        let empty: &Inner<[T]> = &Inner::new([]);
+        unsafe {
+            let start = ::alloc_with_tail(empty, empty.value.get() as *const T, v);
+            SCell(Rc::from(Box::from_raw(ptr::slice_from_raw_parts_mut(start, len) as *mut Inner<[T]>)))
+        }
+    }
+}
+
+impl SCell<dyn Any> {
+    /// Attempts to downcast the contents to a concrete type, returning a handle to the same instance on success
+    ///
+    /// Checking the type borrows the contents, so this panics if they are currently mutably borrowed.
+    #[inline]
+    pub fn downcast<U: Any>(self) -> Result<SCell<U>, Self> {
+        if self.borrow().is::<U>() {
+            let ptr = Rc::into_raw(self.0) as *const Inner<U>;
+            Ok(SCell(unsafe{Rc::from_raw(ptr)}))
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl<T> SWeak<T> {
+    #[inline]
+    pub fn new() -> Self {
+        SWeak(Weak::new())
+    }
+}
+
+impl<T: ?Sized> SWeak<T> {
+    #[inline]
+    pub fn upgrade(&self) -> Option<SCell<T>> {
+        self.0.upgrade().map(SCell)
     }
 }
 
@@ -42,73 +562,132 @@ impl<T: ?Sized> Clone for SCell<T> {
     }
 }
 
-impl<T: ?Sized> Pointer for SCell<T> {
+impl<T: ?Sized> Clone for SWeak<T> {
     #[inline]
-    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        self.0.fmt(f)
+    fn clone(&self) -> Self {
+        SWeak(self.0.clone())
     }
 }
 
-impl<'a, T: 'a + ?Sized> Deref for Ref<'a, T> {
-    type Target = T;
+impl<T> Default for SWeak<T> {
+    #[inline]
+    fn default() -> Self {
+        SWeak::new()
+    }
+}
+
+/// This reallocates the contents to make room for the debug borrow flag, so it fails and gives the `Rc` back if there
+/// are other handles to it.
+impl<T> TryFrom<Rc<UnsafeCell<T>>> for SCell<T> {
+    type Error = Rc<UnsafeCell<T>>;
 
     #[inline]
-    fn deref(&self) -> &T {
-        &*self.0
+    fn try_from(rc: Rc<UnsafeCell<T>>) -> Result<Self, Self::Error> {
+        Rc::try_unwrap(rc).map(|cell| SCell::new(cell.into_inner()))
     }
 }
 
-impl<T: ?Sized> PartialEq for SCell<T>
-where T: PartialEq
-{
+/// This reallocates the contents into the unchecked layout, so it fails and gives the `Rc` back if there are other
+/// handles to it. The checked backend implements `From` instead, which `TryFrom` also covers.
+impl<T> TryFrom<Rc<RefCell<T>>> for SCell<T> {
+    type Error = Rc<RefCell<T>>;
+
     #[inline]
-    fn eq(&self, other: &Self) -> bool {
-        *self.borrow() == *other.borrow()
+    fn try_from(rc: Rc<RefCell<T>>) -> Result<Self, Self::Error> {
+        Rc::try_unwrap(rc).map(|cell| SCell::new(cell.into_inner()))
     }
+}
+
+/// This reallocates the contents, so it fails and gives the handle back if there are other `SCell` handles to them.
+impl<T> TryFrom<SCell<T>> for Rc<UnsafeCell<T>> {
+    type Error = SCell<T>;
 
     #[inline]
-    fn ne(&self, other: &Self) -> bool {
-        *self.borrow() != *other.borrow()
+    fn try_from(cell: SCell<T>) -> Result<Self, Self::Error> {
+        SCell::try_unwrap(cell).map(|t| Rc::new(UnsafeCell::new(t)))
     }
 }
 
-impl<T: ?Sized> Eq for SCell<T> where T: Eq {}
+/// This reallocates the contents, so it fails and gives the handle back if there are other `SCell` handles to them.
+/// The checked backend implements `From` instead, which `TryFrom` also covers.
+impl<T> TryFrom<SCell<T>> for Rc<RefCell<T>> {
+    type Error = SCell<T>;
+
+    #[inline]
+    fn try_from(cell: SCell<T>) -> Result<Self, Self::Error> {
+        cell.try_into_rc()
+    }
+}
+
+/// With the `auto-deref` feature, `SCell` dereferences to its contents without a guard.
+///
+/// This is the same reference a `Ref` holds in release, but nothing marks the contents as borrowed while it is alive,
+/// so mutably borrowing them from any handle before it is dropped is undefined behavior, even in debug builds. Debug
+/// builds do assert that the contents aren't mutably borrowed at the moment they are dereferenced.
+#[cfg(feature = "auto-deref")]
+impl<T: ?Sized> Deref for SCell<T> {
+    type Target = T;
 
-impl<T: ?Sized> PartialOrd for SCell<T>
-where T: PartialOrd
-{
     #[inline]
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.borrow().partial_cmp(&*other.borrow())
+    fn deref(&self) -> &T {
+        #[cfg(debug_assertions)]
+        assert!(self.0.borrow.get() >= 0, "already mutably borrowed");
+        unsafe{&*self.0.value.get()}
     }
+}
 
+impl<T: ?Sized> Pointer for SCell<T> {
     #[inline]
-    fn lt(&self, other: &Self) -> bool {
-        *self.borrow() < *other.borrow()
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        Pointer::fmt(&self.0, f)
     }
+}
 
+impl<T: ?Sized> Pointer for SWeak<T> {
     #[inline]
-    fn le(&self, other: &Self) -> bool {
-        *self.borrow() <= *other.borrow()
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        Pointer::fmt(&self.0.as_ptr(), f)
     }
+}
 
+impl<'a, T: 'a + ?Sized> Ref<'a, T> {
+    /// Makes another `Ref` to the same data without borrowing the cell again
+    ///
+    /// This is an associated function rather than `Clone` so that it doesn't shadow `clone` on the contents.
     #[inline]
-    fn gt(&self, other: &Self) -> bool {
-        *self.borrow() > *other.borrow()
+    #[allow(clippy::should_implement_trait)]
+    pub fn clone(orig: &Self) -> Self {
+        Ref(orig.0, orig.1.clone())
     }
 
+    /// Makes a new `Ref` for a component of the borrowed data
+    ///
+    /// This is an associated function so that it doesn't shadow a method on the contents.
     #[inline]
-    fn ge(&self, other: &Self) -> bool {
-        *self.borrow() >= *other.borrow()
+    pub fn map<U: ?Sized, F>(orig: Self, f: F) -> Ref<'a, U>
+        where F: FnOnce(&T) -> &U
+    {
+        Ref(f(orig.0), orig.1)
+    }
+
+    /// Makes a new `Ref` for an optional component of the borrowed data, or returns the original if there is none
+    #[inline]
+    pub fn filter_map<U: ?Sized, F>(orig: Self, f: F) -> Result<Ref<'a, U>, Self>
+        where F: FnOnce(&T) -> Option<&U>
+    {
+        match f(orig.0) {
+            Some(u) => Ok(Ref(u, orig.1)),
+            None => Err(orig),
+        }
     }
 }
 
-impl<T: ?Sized> Ord for SCell<T>
-where T: Ord
-{
+impl<'a, T: 'a + ?Sized> Deref for Ref<'a, T> {
+    type Target = T;
+
     #[inline]
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.borrow().cmp(&*other.borrow())
+    fn deref(&self) -> &T {
+        self.0
     }
 }
 
@@ -121,6 +700,57 @@ impl<'a, T: 'a + ?Sized> Debug for Ref<'a, T>
     }
 }
 
+impl<'a, T: 'a + ?Sized> RefMut<'a, T> {
+    /// Makes a new `RefMut` for a component of the borrowed data
+    ///
+    /// This is an associated function so that it doesn't shadow a method on the contents.
+    #[inline]
+    pub fn map<U: ?Sized, F>(orig: Self, f: F) -> RefMut<'a, U>
+        where F: FnOnce(&mut T) -> &mut U
+    {
+        RefMut(f(orig.0), orig.1)
+    }
+
+    /// Makes a new `RefMut` for an optional component of the borrowed data, or returns the original if there is none
+    #[inline]
+    pub fn filter_map<U: ?Sized, F>(orig: Self, f: F) -> Result<RefMut<'a, U>, Self>
+        where F: FnOnce(&mut T) -> Option<&mut U>
+    {
+        let RefMut(value, borrow) = orig;
+        // The borrow given to `f` is over by the time it returns `None`, but the borrow checker can't see that.
+        let ptr = value as *mut T;
+        match f(unsafe{&mut *ptr}) {
+            Some(u) => Ok(RefMut(u, borrow)),
+            None => Err(RefMut(unsafe{&mut *ptr}, borrow)),
+        }
+    }
+
+    /// Makes a new `RefMut` for a component of the borrowed data, or returns the original along with the error if `f`
+    /// fails
+    #[inline]
+    pub fn try_map<U: ?Sized, E, F>(orig: Self, f: F) -> Result<RefMut<'a, U>, (Self, E)>
+        where F: FnOnce(&mut T) -> Result<&mut U, E>
+    {
+        let RefMut(value, borrow) = orig;
+        // The borrow given to `f` is over by the time it returns an error, but the borrow checker can't see that.
+        let ptr = value as *mut T;
+        match f(unsafe{&mut *ptr}) {
+            Ok(u) => Ok(RefMut(u, borrow)),
+            Err(e) => Err((RefMut(unsafe{&mut *ptr}, borrow), e)),
+        }
+    }
+
+    /// Splits a `RefMut` into two for disjoint components of the borrowed data
+    #[inline]
+    pub fn map_split<U: ?Sized, V: ?Sized, F>(orig: Self, f: F) -> (RefMut<'a, U>, RefMut<'a, V>)
+        where F: FnOnce(&mut T) -> (&mut U, &mut V)
+    {
+        let RefMut(value, borrow) = orig;
+        let (u, v) = f(value);
+        (RefMut(u, borrow.clone()), RefMut(v, borrow))
+    }
+}
+
 impl<'a, T: 'a + ?Sized> Deref for RefMut<'a, T> {
     type Target = T;
 
@@ -145,3 +775,19 @@ impl<'a, T: 'a + ?Sized> Debug for RefMut<'a, T>
         (*self.0).fmt(f)
     }
 }
+
+impl Display for BorrowError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.write_str("already mutably borrowed")
+    }
+}
+
+impl error::Error for BorrowError {}
+
+impl Display for BorrowMutError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.write_str("already borrowed")
+    }
+}
+
+impl error::Error for BorrowMutError {}
@@ -0,0 +1,69 @@
+//! Waiting for a borrow from async code, enabled by the `async` feature.
+//!
+//! Tasks that find an instance borrowed register their `Waker` in a thread-local table keyed by the instance, and
+//! dropping any guard of that instance wakes all of them so they can try again. `SCell` can't be shared between
+//! threads, so the guard that wakes a task is always dropped on the thread it registered on.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use alloc::vec::Vec;
+use {RefMut, SCell};
+
+thread_local!(static WAITERS: RefCell<HashMap<*const (), Vec<Waker>>> = RefCell::new(HashMap::new()));
+
+/// Registers `waker` to be woken when a guard of the instance identified by `key` is dropped
+fn wait(key: *const (), waker: &Waker) {
+    WAITERS.with(|waiters| {
+        let mut waiters = waiters.borrow_mut();
+        let wakers = waiters.entry(key).or_default();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    });
+}
+
+/// Wakes every task waiting on the instance identified by `key`
+///
+/// The unchecked backend's guards only call this when `debug_assertions` are enabled, since borrows can't fail in
+/// release.
+#[cfg_attr(all(feature = "unchecked", not(debug_assertions)), allow(dead_code))]
+pub fn wake(key: *const ()) {
+    // The table is released before waking so that a task that is polled right away can register again.
+    let wakers = WAITERS.with(|waiters| waiters.borrow_mut().remove(&key));
+    for waker in wakers.into_iter().flatten() {
+        waker.wake();
+    }
+}
+
+/// A future that resolves to a mutable borrow of an `SCell` once it is no longer borrowed, returned by
+/// `SCell::borrow_mut_async`
+pub struct BorrowMutFuture<'a, T: 'a + ?Sized>(&'a SCell<T>);
+
+impl<T: ?Sized> SCell<T> {
+    /// Mutably borrows the contents once they are no longer borrowed, yielding to the executor until then
+    ///
+    /// Dropping any guard of this instance, shared or mutable, wakes every task waiting on it, and the ones that don't
+    /// get the borrow go back to waiting. The unchecked backend only tracks borrows when `debug_assertions` are
+    /// enabled, so in release the first poll is always `Ready`, even while other guards are alive.
+    #[inline]
+    pub fn borrow_mut_async(&self) -> BorrowMutFuture<'_, T> {
+        BorrowMutFuture(self)
+    }
+}
+
+impl<'a, T: 'a + ?Sized> Future for BorrowMutFuture<'a, T> {
+    type Output = RefMut<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<RefMut<'a, T>> {
+        match self.0.try_borrow_mut() {
+            Ok(guard) => Poll::Ready(guard),
+            Err(_) => {
+                wait(self.0.wait_key(), cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}